@@ -32,6 +32,21 @@ const ANT_PHEROMONE_BASE_INTENSITY: f32 = 1.;
 const ANT_TIME_BETWEEN_STATE_CHECKS: f32 = 0.1;
 pub const ANT_RANDOM_WALK_MAX_ROTATION: f32 = PI / 4.;
 const DEFAULT_ANT_COLOR: Color = WHITE;
+const DRAW_INTENT_LINES: bool = true; // draw where a sample of the ants is heading when in debug mode
+const INTENT_LINE_SAMPLE_FRACTION: f32 = 0.1; // share of ants that get an intent line
+const INTENT_LINE_LENGTH: f32 = 40.;
+const FRESH_FOOD_TRAIL_BOOST: f32 = 0.; // how much stronger trails from perfectly fresh food are, 0 disables it
+const CARRYING_FOOD_SPEED_PENALTY: f32 = 0.; // how much slower ants move while carrying food, from 0 to 1
+const LAZY_ANT_FRACTION: f32 = 0.; // share of ants that idle around the nest instead of foraging
+const LAZY_ANT_SPEED_MULTIPLIER: f32 = 0.1;
+const LAZY_ANT_ACTIVATION_INTENSITY: f32 = 2.; // food pheromone intensity that gets a lazy ant foraging
+const CAREFUL_SEARCH: bool = false; // searching ants slow down and turn less while food pheromones get stronger
+const CAREFUL_SEARCH_SPEED_MULTIPLIER: f32 = 0.5;
+const CAREFUL_SEARCH_ROTATION_MULTIPLIER: f32 = 0.5; // applies to random turns right after losing the scent
+const REMEMBER_FOOD_LOCATION: bool = false; // ants without a trail to follow head back to where they last found food
+const NEST_DISPERSAL: bool = false; // searching ants inside the nest head straight out of it instead of milling around
+const NEST_EDGE_DELIVERY: bool = false; // carrying ants near the nest head for its nearest edge instead of piling onto the center
+const NEST_EDGE_APPROACH_CELLS: f32 = 4.; // how close to the nest, in cells, carrying ants have to be to head for its edge
 
 #[derive(Eq, PartialEq, Copy, Clone)]
 pub enum AntState {
@@ -40,25 +55,100 @@ pub enum AntState {
     LookingForFood,
 }
 
+/// Where the ant ended up after a tick, the pheromone it dropped and the action it took, if any
+pub type AntTickResult = (GridLocation, Option<Pheromone>, Option<AntActionTaken>);
+
+#[derive(Eq, PartialEq, Copy, Clone)]
+pub enum AntActivity {
+    Active,
+    Lazy, // hangs around the nest until it senses a strong food trail
+}
+
 pub enum AntActionTaken {
     PickedUpFood,
     DroppedOffFood,
     HitTerrain,
 }
 
-pub struct Ant<'a> {
-    tileset: &'a Texture2D,
-    animated_sprite: AnimatedSprite,
-    animation_count: usize,
+#[derive(Clone)]
+pub struct Ant {
     rotation: f32,
     rect: Rect,
     move_speed: f32,
     distance_since_last_pheromone: f32,
     state: AntState,
+    activity: AntActivity,
     pheromone_intensity: f32,
     dt_since_last_update: f32, // how long ago the ant last checked its bearings
     search_radius: f32,
     distance_between_pheromones: f32,
+    carrying_food_speed_penalty: f32,
+    fresh_food_trail_boost: f32,
+    nest_edge_delivery: bool,
+    nest_dispersal: bool,
+    careful_search: bool,
+    homing_in: bool, // sensed food pheromones got stronger since the previous check
+    last_sensed_food_intensity: f32,
+    remember_food_location: bool,
+    last_food_loc: Option<GridLocation>, // where the ant last picked up food
+}
+
+/// Everything the renderer needs to know about an ant, decoupled from the simulation state
+#[derive(Copy, Clone)]
+pub struct AntSnapshot {
+    rotation: f32,
+    rect: Rect,
+    state: AntState,
+    search_radius: f32,
+}
+
+impl AntSnapshot {
+    pub fn state(&self) -> AntState {
+        self.state
+    }
+
+    fn color(&self) -> Color {
+        match self.state {
+            AntState::CarryingFood => FOOD_COLOR,
+            AntState::LookingForFood => DEFAULT_ANT_COLOR,
+        }
+    }
+
+    pub fn rect(&self) -> &Rect {
+        &self.rect
+    }
+}
+
+/// Render-only state of an ant. Kept out of `Ant` so that the simulation doesn't depend on textures.
+pub struct AntSprite {
+    animated_sprite: AnimatedSprite,
+    animation_count: usize,
+}
+
+/// Draws a heading line for a sample of the ants to show where the swarm is going as a whole. Ants are
+/// picked by their index, which is stable as ants are never removed, so the sample doesn't flicker.
+pub fn draw_intent_lines(ants: &[AntSnapshot]) {
+    if !(DEBUG && DRAW_INTENT_LINES) {
+        return;
+    }
+
+    for (_, ant) in ants
+        .iter()
+        .enumerate()
+        .filter(|(id, _)| is_intent_sample(*id, INTENT_LINE_SAMPLE_FRACTION))
+    {
+        let center = ant.rect.center();
+        let tip = center + Vec2::from_angle(ant.rotation) * INTENT_LINE_LENGTH;
+        draw_line(center.x, center.y, tip.x, tip.y, 1., ant.color());
+    }
+}
+
+fn is_intent_sample(id: usize, sample_fraction: f32) -> bool {
+    if sample_fraction <= 0. {
+        return false;
+    }
+    let stride = (1. / sample_fraction).round().max(1.) as usize;
+    id.is_multiple_of(stride)
 }
 
 fn get_animation_for_idx(idx: u32, frames: u32, fps: u32) -> Animation {
@@ -70,24 +160,39 @@ fn get_animation_for_idx(idx: u32, frames: u32, fps: u32) -> Animation {
     }
 }
 
-impl<'a> Ant<'a> {
-    pub fn draw(&mut self) {
-        let ant_sprite = &mut self.animated_sprite;
+impl AntSprite {
+    pub fn new() -> Self {
+        let frame_counts: [u32; 8] = [8, 8, 8, 8, 8, 8, 8, 6];
+        let animated_sprite = AnimatedSprite::new(
+            ANT_BASE_WIDTH,
+            ANT_BASE_HEIGHT,
+            &frame_counts
+                .iter()
+                .enumerate()
+                .map(|(i, frames)| get_animation_for_idx(i as u32, *frames, ANT_ANIMATION_FPS))
+                .collect::<Vec<Animation>>(),
+            true,
+        );
 
-        let color = match self.state {
-            AntState::CarryingFood => FOOD_COLOR,
-            AntState::LookingForFood => DEFAULT_ANT_COLOR,
-        };
+        Self {
+            animated_sprite,
+            animation_count: frame_counts.len(),
+        }
+    }
+
+    pub fn draw(&mut self, tileset: &Texture2D, ant: &AntSnapshot) {
+        let ant_sprite = &mut self.animated_sprite;
+        let rect = ant.rect();
 
         draw_texture_ex(
-            self.tileset,
-            self.rect.x,
-            self.rect.y,
-            color,
+            tileset,
+            rect.x,
+            rect.y,
+            ant.color(),
             DrawTextureParams {
                 source: Some(ant_sprite.frame().source_rect),
                 dest_size: Some(ant_sprite.frame().dest_size * ANT_SIZE_MULTIPLIER),
-                rotation: self.rotation + ANT_SPRITE_ROTATION_CORRECTION,
+                rotation: ant.rotation + ANT_SPRITE_ROTATION_CORRECTION,
                 ..DrawTextureParams::default()
             },
         );
@@ -95,30 +200,30 @@ impl<'a> Ant<'a> {
         if DEBUG {
             // search radius
             draw_circle_lines(
-                self.rect.center().x,
-                self.rect.center().y,
-                self.search_radius,
+                rect.center().x,
+                rect.center().y,
+                ant.search_radius,
                 2.,
                 GREEN,
             );
 
             // ant bounding box
-            draw_rectangle(self.rect.x, self.rect.y, self.rect.w, self.rect.h, WHITE);
+            draw_rectangle(rect.x, rect.y, rect.w, rect.h, WHITE);
 
             // draw direction of the ant
-            let direction = Vec2::new(self.rotation.cos(), self.rotation.sin());
+            let direction = Vec2::new(ant.rotation.cos(), ant.rotation.sin());
             draw_line(
-                self.rect.center().x,
-                self.rect.center().y,
-                self.rect.center().x + direction.x * 20.,
-                self.rect.center().y + direction.y * 20.,
+                rect.center().x,
+                rect.center().y,
+                rect.center().x + direction.x * 20.,
+                rect.center().y + direction.y * 20.,
                 1.,
                 GREEN,
             );
 
             // draw rotation value
-            let msg = format!("Rotation: {}", self.rotation);
-            draw_text(msg.as_str(), self.rect.x, self.rect.y, 10., WHITE);
+            let msg = format!("Rotation: {}", ant.rotation);
+            draw_text(msg.as_str(), rect.x, rect.y, 10., WHITE);
         }
 
         // loop animation
@@ -129,26 +234,13 @@ impl<'a> Ant<'a> {
             ant_sprite.update();
         }
     }
+}
 
-    pub fn new(x: f32, y: f32, tileset: &'a Texture2D, grid: &WorldGrid) -> Self {
-        let frame_counts: [u32; 8] = [8, 8, 8, 8, 8, 8, 8, 6];
-        let animated_sprite = AnimatedSprite::new(
-            ANT_BASE_WIDTH,
-            ANT_BASE_HEIGHT,
-            &frame_counts
-                .iter()
-                .enumerate()
-                .map(|(i, frames)| get_animation_for_idx(i as u32, *frames, ANT_ANIMATION_FPS))
-                .collect::<Vec<Animation>>(),
-            true,
-        );
-
+impl Ant {
+    pub fn new(x: f32, y: f32, grid: &WorldGrid) -> Self {
         let distance_between_pheromones = CELLS_WIDTHS_BETWEEN_PHEROMONES * grid.cell_width;
 
         Ant {
-            tileset,
-            animated_sprite,
-            animation_count: frame_counts.len(),
             rotation: gen_range(-PI, PI),
             move_speed: gen_range(1.0 - ANT_SPEED_RANDOM_FACTOR, 1.0 + ANT_SPEED_RANDOM_FACTOR)
                 * BASE_ANT_MOVE_SPEED,
@@ -160,13 +252,58 @@ impl<'a> Ant<'a> {
             ),
             distance_since_last_pheromone: 0.,
             state: AntState::LookingForFood,
+            activity: if gen_range(0., 1.) < LAZY_ANT_FRACTION {
+                AntActivity::Lazy
+            } else {
+                AntActivity::Active
+            },
             pheromone_intensity: ANT_PHEROMONE_BASE_INTENSITY,
             dt_since_last_update: gen_range(0., ANT_TIME_BETWEEN_STATE_CHECKS),
             search_radius: ANT_GRID_SENSES_PERCENT * GRID_WIDTH as f32 * grid.cell_width,
             distance_between_pheromones,
+            carrying_food_speed_penalty: CARRYING_FOOD_SPEED_PENALTY,
+            fresh_food_trail_boost: FRESH_FOOD_TRAIL_BOOST,
+            nest_edge_delivery: NEST_EDGE_DELIVERY,
+            nest_dispersal: NEST_DISPERSAL,
+            careful_search: CAREFUL_SEARCH,
+            homing_in: false,
+            last_sensed_food_intensity: 0.,
+            remember_food_location: REMEMBER_FOOD_LOCATION,
+            last_food_loc: None,
+        }
+    }
+
+    /// Returns the ant facing in the given direction instead of a random one
+    pub fn with_rotation(self, rotation: f32) -> Self {
+        Self {
+            rotation: normalize_angle(rotation),
+            ..self
+        }
+    }
+
+    pub fn rect(&self) -> &Rect {
+        &self.rect
+    }
+
+    pub fn state(&self) -> AntState {
+        self.state
+    }
+
+    pub fn snapshot(&self) -> AntSnapshot {
+        AntSnapshot {
+            rotation: self.rotation,
+            rect: self.rect,
+            state: self.state,
+            search_radius: self.search_radius,
         }
     }
 
+    /// Keeps the ant walking straight until its next check is due, eg to make tests deterministic
+    #[cfg(test)]
+    pub fn delay_next_check(&mut self) {
+        self.dt_since_last_update = 0.;
+    }
+
     /// Returns the angle to the target pheromone
     fn get_target_angle(&self, pheromone: Pheromone) -> f32 {
         let direction = (pheromone.rect().center() - self.rect.center()).normalize_or_zero();
@@ -178,12 +315,26 @@ impl<'a> Ant<'a> {
         self.rotation = normalize_angle(target_angle);
     }
 
+    fn current_move_speed(&self) -> f32 {
+        let speed = match self.activity {
+            AntActivity::Active => self.move_speed,
+            AntActivity::Lazy => self.move_speed * LAZY_ANT_SPEED_MULTIPLIER,
+        };
+
+        match self.state {
+            AntState::CarryingFood => speed * (1. - self.carrying_food_speed_penalty),
+            AntState::LookingForFood if self.homing_in => speed * CAREFUL_SEARCH_SPEED_MULTIPLIER,
+            AntState::LookingForFood => speed,
+        }
+    }
+
     /// Walks straight given its current rotation and respecting the boundaries of the world
     fn walk_straight(&mut self, bounding_box: &Rect, dt: f32) {
         let direction = Vec2::new(self.rotation.cos(), self.rotation.sin());
+        let move_speed = self.current_move_speed();
 
-        self.rect.x += direction.x * self.move_speed * dt;
-        self.rect.y += direction.y * self.move_speed * dt;
+        self.rect.x += direction.x * move_speed * dt;
+        self.rect.y += direction.y * move_speed * dt;
 
         // keep the ant within world boundary
         if self.rect.x < bounding_box.x {
@@ -201,6 +352,13 @@ impl<'a> Ant<'a> {
         }
     }
 
+    /// Rolls the ant back to its state before the last tick and turns it away, eg when the cell it
+    /// walked into turned out to be full
+    pub fn undo_tick(&mut self, previous: Ant) {
+        *self = previous;
+        self.bounce_off();
+    }
+
     /// Turn in a random new direction to avoid collision
     fn bounce_off(&mut self) {
         // TODO: revisit and refactor
@@ -221,22 +379,82 @@ impl<'a> Ant<'a> {
         }
 
         self.dt_since_last_update = 0.; // reset behavior change timer
-        let candidate_pheromones = match self.state {
-            AntState::LookingForFood => grid.pheromones(PheromoneType::Food),
-            AntState::CarryingFood => grid.pheromones(PheromoneType::Home),
+
+        if self.activity == AntActivity::Lazy {
+            // lazy ants only join in once they sense strong recruitment to a food source
+            let recruitment = grid
+                .pheromones(PheromoneType::Food)
+                .get_pheromone_to_target(grid, &self.rect, self.rotation, self.search_radius);
+            if recruitment.is_some_and(|ph| ph.intensity() >= LAZY_ANT_ACTIVATION_INTENSITY) {
+                self.activity = AntActivity::Active;
+            }
+        }
+
+        let candidate_pheromones = match (self.state, self.activity) {
+            // idle ants keep to the nest by following home pheromones
+            (AntState::LookingForFood, AntActivity::Lazy) => grid.pheromones(PheromoneType::Home),
+            (AntState::LookingForFood, AntActivity::Active) => grid.pheromones(PheromoneType::Food),
+            (AntState::CarryingFood, _) => grid.pheromones(PheromoneType::Home),
         };
 
-        let target_angle = if let Some(pheromone) = candidate_pheromones.get_pheromone_to_target(
+        if self.state == AntState::CarryingFood && self.nest_edge_delivery {
+            if let Some(target) = self.nest_edge_target(grid) {
+                let direction = grid.get_rect_from_loc(target).center() - self.rect.center();
+                self.snap_towards(direction.y.atan2(direction.x));
+                self.walk_straight(grid.bounding_box(), dt);
+                return;
+            }
+        }
+
+        let target_pheromone = candidate_pheromones.get_pheromone_to_target(
             grid,
             &self.rect,
             self.rotation,
             self.search_radius,
-        ) {
+        );
+
+        let was_homing_in = self.homing_in;
+        if self.careful_search
+            && self.state == AntState::LookingForFood
+            && self.activity == AntActivity::Active
+        {
+            // closing in on a food source, slow down so as not to overshoot it
+            let sensed_intensity = target_pheromone.map_or(0., |ph| ph.intensity());
+            self.homing_in = sensed_intensity > self.last_sensed_food_intensity;
+            self.last_sensed_food_intensity = sensed_intensity;
+        }
+
+        if self.state == AntState::LookingForFood
+            && self.activity == AntActivity::Active
+            && self.nest_dispersal
+        {
+            if let Some(outward) = self.nest_exit_direction(grid) {
+                self.snap_towards(outward.y.atan2(outward.x));
+                self.walk_straight(grid.bounding_box(), dt);
+                return;
+            }
+        }
+
+        let remembered_food = match (self.state, self.activity) {
+            (AntState::LookingForFood, AntActivity::Active) => self.remembered_food_target(grid),
+            _ => None,
+        };
+
+        let target_angle = if let Some(pheromone) = target_pheromone {
             // if we found a pheromone in our field of view, turn towards it
             self.get_target_angle(pheromone)
+        } else if let Some(food_center) = remembered_food {
+            // no trail to follow, go back to where we found food last time
+            let direction = food_center - self.rect.center();
+            direction.y.atan2(direction.x)
         } else {
-            // otherwise turn randomly
-            self.rotation + gen_range(-ANT_RANDOM_WALK_MAX_ROTATION, ANT_RANDOM_WALK_MAX_ROTATION)
+            // otherwise turn randomly, searching the area more closely if we just lost a strengthening scent
+            let max_rotation = if was_homing_in {
+                ANT_RANDOM_WALK_MAX_ROTATION * CAREFUL_SEARCH_ROTATION_MULTIPLIER
+            } else {
+                ANT_RANDOM_WALK_MAX_ROTATION
+            };
+            self.rotation + gen_range(-max_rotation, max_rotation)
         };
 
         // walk in the direction we picked
@@ -244,11 +462,61 @@ impl<'a> Ant<'a> {
         self.walk_straight(grid.bounding_box(), dt);
     }
 
-    pub fn tick(
-        &mut self,
-        grid: &WorldGrid,
-        dt: f32,
-    ) -> (GridLocation, Option<Pheromone>, Option<AntActionTaken>) {
+    /// Returns where the ant last found food, forgetting about it once the ant can see the food is gone
+    fn remembered_food_target(&mut self, grid: &WorldGrid) -> Option<Vec2> {
+        let loc = self.last_food_loc?;
+        let food_center = grid.get_rect_from_loc(loc).center();
+
+        let depleted = !matches!(grid.get_cell_for_loc(loc).cell_type(), CellType::Food(_));
+        if depleted && food_center.distance(self.rect.center()) <= self.search_radius {
+            self.last_food_loc = None;
+            return None;
+        }
+
+        Some(food_center)
+    }
+
+    /// Returns the direction pointing away from the nest centroid if the ant is inside the nest
+    fn nest_exit_direction(&self, grid: &WorldGrid) -> Option<Vec2> {
+        let loc = grid.get_grid_location_for_rect(&self.rect)?;
+        if !grid.home_locations().contains(&loc) {
+            return None;
+        }
+
+        let outward = self.rect.center() - grid.nest_centroid()?;
+        if outward.length() < grid.cell_width {
+            // around the centroid, eg at the spawn point, any way out is as good as another
+            return Some(Vec2::from_angle(self.rotation));
+        }
+        Some(outward)
+    }
+
+    /// Returns the closest home cell on the border of the nest if the ant is right by the nest and
+    /// nothing stands in the way, otherwise it's up to the trails to lead the ant home
+    fn nest_edge_target(&self, grid: &WorldGrid) -> Option<GridLocation> {
+        let center = self.rect.center();
+        let (target, distance) = grid
+            .home_locations()
+            .iter()
+            .filter(|loc| grid.is_nest_edge(**loc))
+            .map(|loc| (*loc, grid.get_rect_from_loc(*loc).center().distance(center)))
+            .min_by(|(_, d1), (_, d2)| d1.total_cmp(d2))?;
+        if distance > NEST_EDGE_APPROACH_CELLS * grid.cell_width {
+            return None;
+        }
+
+        // rays stop at terrain, so only a clear line of sight reaches the target
+        let direction = grid.get_rect_from_loc(target).center() - center;
+        grid.get_cells_in_direction(
+            &self.rect,
+            direction.y.atan2(direction.x),
+            self.search_radius,
+        )
+        .contains(&target)
+        .then_some(target)
+    }
+
+    pub fn tick(&mut self, grid: &WorldGrid, dt: f32) -> AntTickResult {
         // walk
         let starting_point = self.rect;
 
@@ -273,7 +541,15 @@ impl<'a> Ant<'a> {
         match current_cell.cell_type() {
             CellType::Food(_) => {
                 self.state = AntState::CarryingFood;
-                self.pheromone_intensity = ANT_PHEROMONE_BASE_INTENSITY;
+                // fresher food is worth recruiting more ants to
+                let freshness = grid.food_freshness(ending_location);
+                self.pheromone_intensity =
+                    ANT_PHEROMONE_BASE_INTENSITY * (1. + self.fresh_food_trail_boost * freshness);
+                self.homing_in = false;
+                self.last_sensed_food_intensity = 0.;
+                if self.remember_food_location {
+                    self.last_food_loc = Some(ending_location);
+                }
             }
             CellType::Home => {
                 self.state = AntState::LookingForFood;
@@ -317,8 +593,283 @@ impl<'a> Ant<'a> {
 
         (ending_location, pheromone, action_taken)
     }
+}
 
-    pub fn state(&self) -> AntState {
-        self.state
+#[test]
+fn test_lazy_ants_stay_near_nest() {
+    let grid = WorldGrid::new(&crate::simulation::nest_locations(), 800., 600.);
+    let nest_center = grid.get_rect_from_loc(GridLocation::new(75, 100)).center();
+
+    let mut ants: Vec<Ant> = std::iter::repeat_with(|| {
+        let mut ant = Ant::new(nest_center.x, nest_center.y, &grid);
+        ant.activity = AntActivity::Lazy;
+        ant
+    })
+    .take(50)
+    .collect();
+
+    for _ in 0..200 {
+        for ant in ants.iter_mut() {
+            ant.tick(&grid, 0.05);
+        }
+    }
+
+    // without any food trails around, none of them wandered off or got activated
+    for ant in &ants {
+        assert!(ant.activity == AntActivity::Lazy);
+        assert!(ant.rect.center().distance(nest_center) < ant.search_radius);
+    }
+}
+
+#[test]
+fn test_carrying_food_slows_ants_down() {
+    let grid = WorldGrid::new(&[], 800., 600.);
+
+    let mut searching = Ant::new(400., 300., &grid);
+    searching.rotation = 0.;
+    searching.move_speed = BASE_ANT_MOVE_SPEED;
+    searching.carrying_food_speed_penalty = 0.5;
+
+    let mut carrying = searching.clone();
+    carrying.state = AntState::CarryingFood;
+
+    let start = searching.rect.center();
+    searching.walk_straight(grid.bounding_box(), 0.1);
+    carrying.walk_straight(grid.bounding_box(), 0.1);
+
+    let searching_distance = searching.rect.center().distance(start);
+    let carrying_distance = carrying.rect.center().distance(start);
+    assert!(carrying_distance < searching_distance);
+    assert!((carrying_distance - searching_distance * 0.5).abs() < 0.001);
+}
+
+#[test]
+fn test_carrying_ants_deliver_to_nest_edge() {
+    let grid = WorldGrid::new(&crate::simulation::nest_locations(), 800., 600.);
+    let nest_center = GridLocation::new(75, 100);
+
+    // approaching the nest from the left, level with its center
+    let start = grid.get_rect_from_loc(GridLocation::new(75, 92)).center();
+    let mut ant = Ant::new(start.x, start.y, &grid);
+    ant.rotation = 0.;
+    ant.state = AntState::CarryingFood;
+    ant.nest_edge_delivery = true;
+
+    let target = ant
+        .nest_edge_target(&grid)
+        .expect("nest is right in front of the ant");
+    assert!(grid.is_nest_edge(target));
+    assert_ne!(target, nest_center);
+    assert_eq!(target, GridLocation::new(75, 95));
+
+    // and it walks there, dropping off food at the edge
+    let mut delivered_at = None;
+    for _ in 0..100 {
+        let (loc, _, action) = ant.tick(&grid, 0.01);
+        if let Some(AntActionTaken::DroppedOffFood) = action {
+            delivered_at = Some(loc);
+            break;
+        }
+    }
+    assert!(grid.is_nest_edge(delivered_at.expect("ant reached the nest")));
+}
+
+#[test]
+fn test_nest_edge_delivery_leaves_terrain_to_trails() {
+    let mut grid = WorldGrid::new(&crate::simulation::nest_locations(), 800., 600.);
+
+    // a wall well outside the nest, the ant behind it follows trails as usual
+    let wall = grid.get_rect_from_loc(GridLocation::new(75, 88)).center();
+    grid.spawn_cells(wall.x, wall.y, CellType::Terrain);
+    let start = grid.get_rect_from_loc(GridLocation::new(75, 82)).center();
+    let mut ant = Ant::new(start.x, start.y, &grid);
+    ant.state = AntState::CarryingFood;
+    ant.nest_edge_delivery = true;
+    assert_eq!(ant.nest_edge_target(&grid), None);
+
+    // terrain eating into the nest's left side, between the ant and the closest edge cells
+    let rubble = grid.get_rect_from_loc(GridLocation::new(75, 97)).center();
+    grid.spawn_cells(rubble.x, rubble.y, CellType::Terrain);
+    let start = grid.get_rect_from_loc(GridLocation::new(75, 94)).center();
+    let mut ant = Ant::new(start.x, start.y, &grid);
+    ant.state = AntState::CarryingFood;
+    ant.nest_edge_delivery = true;
+    assert_eq!(ant.nest_edge_target(&grid), None);
+
+    // without the terrain the ant heads straight for the nest
+    let grid = WorldGrid::new(&crate::simulation::nest_locations(), 800., 600.);
+    assert_eq!(ant.nest_edge_target(&grid), Some(GridLocation::new(75, 95)));
+}
+
+#[test]
+fn test_careful_search_slows_ants_climbing_food_gradient() {
+    // a band of food pheromones around the ant, either getting stronger to the right or flat
+    let field = |intensity_at: fn(usize) -> f32| {
+        let mut grid = WorldGrid::new(&[], 800., 600.);
+        for r in 40..60 {
+            for c in 0..GRID_WIDTH {
+                let loc = GridLocation::new(r, c);
+                let ph =
+                    grid.create_pheromone_for_loc(loc, PheromoneType::Food, intensity_at(c), false);
+                grid.deposit_pheromone(ph);
+            }
+        }
+        grid
+    };
+    let gradient = field(|c| c as f32 + 1.);
+    let flat = field(|_| 1.);
+
+    let start = gradient
+        .get_rect_from_loc(GridLocation::new(50, 50))
+        .center();
+    let mut ant = Ant::new(start.x, start.y, &gradient);
+    ant.rotation = 0.;
+    ant.move_speed = BASE_ANT_MOVE_SPEED;
+    ant.careful_search = true;
+    ant.dt_since_last_update = ANT_TIME_BETWEEN_STATE_CHECKS;
+
+    let walk = |mut ant: Ant, grid: &WorldGrid| {
+        for _ in 0..10 {
+            ant.walk_to_pheromones(grid, 0.05);
+        }
+        ant
+    };
+    let climbing = walk(ant.clone(), &gradient);
+    let on_flat_field = walk(ant, &flat);
+
+    assert!(climbing.homing_in);
+    assert!(!on_flat_field.homing_in);
+    assert!(climbing.current_move_speed() < on_flat_field.current_move_speed());
+}
+
+#[test]
+fn test_ants_head_back_to_remembered_food() {
+    let mut grid = WorldGrid::new(&[], 800., 600.);
+    let food_loc = GridLocation::new(75, 150);
+    let food_center = grid.get_rect_from_loc(food_loc).center();
+    grid.spawn_cells(food_center.x, food_center.y, CellType::Food(1));
+
+    // facing away from the food, which is too far away to sense
+    let start = grid.get_rect_from_loc(GridLocation::new(75, 100)).center();
+    let mut ant = Ant::new(start.x, start.y, &grid);
+    ant.rotation = PI;
+    ant.remember_food_location = true;
+    ant.last_food_loc = Some(food_loc);
+    ant.dt_since_last_update = ANT_TIME_BETWEEN_STATE_CHECKS;
+
+    let mut forgetful = ant.clone();
+    forgetful.last_food_loc = None;
+
+    ant.walk_to_pheromones(&grid, 0.05);
+    forgetful.walk_to_pheromones(&grid, 0.05);
+    assert!(ant.rect.center().x > start.x);
+    assert!(ant.rotation.abs() < 0.01);
+    assert!(forgetful.rect.center().x < start.x);
+
+    // once the food is gone, the ant forgets about it when it gets close enough to notice
+    grid.spawn_cells(food_center.x, food_center.y, CellType::Empty);
+    ant.dt_since_last_update = ANT_TIME_BETWEEN_STATE_CHECKS;
+    ant.walk_to_pheromones(&grid, 0.05);
+    assert_eq!(ant.last_food_loc, Some(food_loc));
+
+    let nearby = grid.get_rect_from_loc(GridLocation::new(75, 145)).center();
+    ant.rect.x = nearby.x - ant.rect.w / 2.;
+    ant.rect.y = nearby.y - ant.rect.h / 2.;
+    ant.dt_since_last_update = ANT_TIME_BETWEEN_STATE_CHECKS;
+    ant.walk_to_pheromones(&grid, 0.05);
+    assert_eq!(ant.last_food_loc, None);
+}
+
+#[test]
+fn test_intent_line_sample() {
+    let sample: Vec<usize> = (0..1000).filter(|id| is_intent_sample(*id, 0.1)).collect();
+    assert_eq!(sample.len(), 100);
+    // ants that are already sampled stay sampled as more of the colony spawns in
+    let early_sample: Vec<usize> = (0..500).filter(|id| is_intent_sample(*id, 0.1)).collect();
+    assert_eq!(
+        early_sample,
+        sample
+            .iter()
+            .copied()
+            .filter(|id| *id < 500)
+            .collect::<Vec<usize>>()
+    );
+
+    assert!((0..1000).all(|id| is_intent_sample(id, 1.)));
+    assert!(!(0..1000).any(|id| is_intent_sample(id, 0.)));
+}
+
+#[test]
+fn test_fresh_food_lays_stronger_trails() {
+    let mut grid = WorldGrid::new(&[], 800., 600.);
+    let stale_food = grid.get_rect_from_loc(GridLocation::new(30, 30)).center();
+    grid.spawn_cells(stale_food.x, stale_food.y, CellType::Food(1));
+    for _ in 0..100 {
+        grid.tick(0.1);
     }
+    let fresh_food = grid.get_rect_from_loc(GridLocation::new(30, 100)).center();
+    grid.spawn_cells(fresh_food.x, fresh_food.y, CellType::Food(1));
+
+    let trail_from = |food: Vec2| {
+        let mut ant = Ant::new(food.x, food.y, &grid);
+        ant.fresh_food_trail_boost = 1.;
+        ant.delay_next_check();
+        // drop a pheromone right away
+        ant.distance_since_last_pheromone = ant.distance_between_pheromones;
+        let (_, pheromone, action) = ant.tick(&grid, 0.001);
+        assert!(matches!(action, Some(AntActionTaken::PickedUpFood)));
+        pheromone.expect("ant should lay a pheromone").intensity()
+    };
+
+    let stale_trail = trail_from(stale_food);
+    let fresh_trail = trail_from(fresh_food);
+    assert!(stale_trail > ANT_PHEROMONE_BASE_INTENSITY);
+    assert!(fresh_trail > stale_trail);
+    assert_eq!(fresh_trail, ANT_PHEROMONE_BASE_INTENSITY * 2.);
+}
+
+#[test]
+fn test_searching_ants_disperse_from_nest() {
+    let grid = WorldGrid::new(&crate::simulation::nest_locations(), 800., 600.);
+    let centroid = grid.nest_centroid().unwrap();
+
+    // inside the nest, up and to the left of its center, facing inwards
+    let start = grid.get_rect_from_loc(GridLocation::new(72, 97)).center();
+    let mut ant = Ant::new(start.x, start.y, &grid);
+    ant.rotation = PI / 4.;
+    ant.nest_dispersal = true;
+    ant.dt_since_last_update = ANT_TIME_BETWEEN_STATE_CHECKS;
+
+    ant.walk_to_pheromones(&grid, 0.05);
+    let outward = (start - centroid).normalize();
+    let heading = Vec2::from_angle(ant.rotation);
+    assert!(heading.dot(outward) > 0.99);
+    assert!(ant.rect.center().distance(centroid) > start.distance(centroid));
+
+    // fresh ants at the spawn point keep their own headings rather than all leaving the same way
+    let spawn_point = crate::simulation::Simulation::new(800., 600.).ant_snapshots()[0]
+        .rect
+        .center();
+    let headings: Vec<Vec2> = (0..20)
+        .map(|_| {
+            let mut ant = Ant::new(spawn_point.x, spawn_point.y, &grid);
+            ant.nest_dispersal = true;
+            ant.dt_since_last_update = ANT_TIME_BETWEEN_STATE_CHECKS;
+            let heading = Vec2::from_angle(ant.rotation);
+
+            ant.walk_to_pheromones(&grid, 0.05);
+            assert!(Vec2::from_angle(ant.rotation).dot(heading) > 0.999);
+            heading
+        })
+        .collect();
+    assert!(
+        headings
+            .iter()
+            .any(|heading| heading.dot(headings[0]) < 0.9)
+    );
+
+    // outside of the nest they search as usual
+    let outside = grid.get_rect_from_loc(GridLocation::new(60, 90)).center();
+    let ant = Ant::new(outside.x, outside.y, &grid);
+    assert_eq!(ant.nest_exit_direction(&grid), None);
 }
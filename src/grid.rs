@@ -1,12 +1,20 @@
-use std::collections::HashSet;
+use std::cmp::Ordering;
+use std::collections::{BinaryHeap, HashMap, HashSet};
+use std::io::{self, Write};
+use std::path::Path;
+use std::sync::Arc;
+
+use image::{GrayImage, ImageResult, Luma, Rgb, RgbImage};
 
 use macroquad::color::{Color, PURPLE, WHITE, YELLOW};
 use macroquad::prelude::{get_fps, Rect, Vec2};
+use macroquad::rand::gen_range;
 use macroquad::text::draw_text;
 
-use crate::ant::{Ant, AntActionTaken, AntState};
+use crate::DEBUG;
+use crate::ant::{AntActionTaken, AntSnapshot, AntState};
 use crate::pheromone::{Pheromone, Pheromones, PheromoneType, SPECIAL_PHEROMONE_INTENSITY};
-use crate::util::RectExtensions;
+use crate::util::{RectExtensions, draw_arrow, value_noise};
 
 // grid
 pub const GRID_WIDTH: usize = 200;
@@ -16,9 +24,34 @@ pub const GRID_HEIGHT: usize = 150;
 pub const FOOD_COLOR: Color = Color::new(1.00, 0.3, 0.00, 1.00);
 pub const NEST_COLOR: Color = PURPLE;
 const TERRAIN_COLOR: Color = YELLOW;
+const GRADIENT_ARROW_COLOR: Color = WHITE;
+const COMBINED_PHEROMONE_COLOR: Color = Color::new(0.20, 0.90, 0.90, 1.00);
+const MAX_COMBINED_PHEROMONE_OPACITY: f32 = 0.75;
+const TRAFFIC_HEATMAP_COLOR: Color = Color::new(0.30, 0.80, 1.00, 1.00);
 
 // food
 pub const FOOD_CONSUMPTION_LIMIT: u32 = 10;
+const FOOD_FRESHNESS_DECAY_RATE: f32 = 0.05; // how quickly food goes stale, freshness starts at 1 when placed
+const FOOD_GLOW: bool = false; // draw a faint halo behind food cells to make them easier to spot
+const FOOD_GLOW_MAX_SPREAD: f32 = 1.5; // how many cell sizes the glow extends past a full food cell
+const FOOD_GLOW_OPACITY: f32 = 0.15;
+
+// pheromones
+const COMBINED_PHEROMONE_FIELD: bool = false; // draw food and home pheromones as a single activity heat map
+const TERRAIN_DEPOSIT_PENALTY: f32 = 0.; // how much weaker ant deposits are next to terrain, from 0 to 1
+const DEPOSIT_JITTER: f32 = 0.; // how far deposits stray from the cell center, as a fraction of half a cell (below 1)
+const TERRAIN_SHADOWS: bool = false; // draw pheromones next to terrain darker to give obstacles some depth
+const TERRAIN_SHADOW_BRIGHTNESS: f32 = 0.6;
+const WEATHERING_STRENGTH: f32 = 0.; // how much decay rates vary across the map, from 0 (uniform) to 1
+const WEATHERING_SCALE: f32 = 20.; // rough size of weathering regions, in cells
+
+// debug
+const DRAW_HOME_GRADIENT: bool = true; // draw arrows pointing towards the nest when in debug mode
+const GRADIENT_ARROW_SPACING: usize = 5; // sample the gradient every N cells to keep the overlay cheap
+
+// export
+pub const SUMMARY_IMAGE_CELL_SIZE: u32 = 4; // width and height of each cell in summary images, in pixels
+const HEAT_FRAME_FULL_INTENSITY: f32 = 5.; // combined pheromone intensity drawn as white in heat frames
 
 // UI
 const FONT_SIZE: f32 = 16.;
@@ -45,7 +78,13 @@ pub struct WorldCell {
 
 impl WorldCell {
     fn draw(&self) {
-        if let Some(color) = match self.cell_type {
+        if let Some(color) = self.color() {
+            self.rect.draw_rectangle(color);
+        }
+    }
+
+    fn color(&self) -> Option<Color> {
+        match self.cell_type {
             CellType::Food(remaining_amount) => Some(Color {
                 a: remaining_amount as f32 / FOOD_CONSUMPTION_LIMIT as f32,
                 ..FOOD_COLOR
@@ -53,8 +92,16 @@ impl WorldCell {
             CellType::Home => Some(NEST_COLOR),
             CellType::Terrain => Some(TERRAIN_COLOR),
             CellType::Empty => None, // don't draw empty cells
-        } {
-            self.rect.draw_rectangle(color);
+        }
+    }
+
+    fn draw_glow(&self) {
+        if let CellType::Food(remaining_amount) = self.cell_type {
+            let color = Color {
+                a: FOOD_GLOW_OPACITY * remaining_amount as f32 / FOOD_CONSUMPTION_LIMIT as f32,
+                ..FOOD_COLOR
+            };
+            food_glow_rect(&self.rect, remaining_amount).draw_rectangle(color);
         }
     }
 
@@ -63,7 +110,60 @@ impl WorldCell {
     }
 }
 
-#[derive(Eq, Hash, PartialEq, Copy, Clone, Default)]
+/// Returns the halo drawn behind a food cell, which shrinks as the food gets consumed
+fn food_glow_rect(cell_rect: &Rect, remaining_amount: u32) -> Rect {
+    let remaining_ratio =
+        remaining_amount.min(FOOD_CONSUMPTION_LIMIT) as f32 / FOOD_CONSUMPTION_LIMIT as f32;
+    let spread_x = cell_rect.w * FOOD_GLOW_MAX_SPREAD * remaining_ratio;
+    let spread_y = cell_rect.h * FOOD_GLOW_MAX_SPREAD * remaining_ratio;
+
+    Rect::new(
+        cell_rect.x - spread_x,
+        cell_rect.y - spread_y,
+        cell_rect.w + spread_x * 2.,
+        cell_rect.h + spread_y * 2.,
+    )
+}
+
+/// Returns noise driven decay multipliers for every cell, varying between `1 - strength` and `1 + strength`
+fn weathering_map(strength: f32, seed: u32) -> Vec<[f32; GRID_HEIGHT]> {
+    (0..GRID_WIDTH)
+        .map(|c| {
+            let mut column = [1.; GRID_HEIGHT];
+            for (r, multiplier) in column.iter_mut().enumerate() {
+                let noise = value_noise(c as f32, r as f32, WEATHERING_SCALE, seed);
+                *multiplier = 1. + strength * (noise * 2. - 1.);
+            }
+            column
+        })
+        .collect()
+}
+
+/// A cell reached by the shortest trail search, ordered so that the heap pops the shortest route first
+#[derive(PartialEq)]
+struct TrailStep {
+    length: f32,
+    loc: GridLocation,
+}
+
+impl Eq for TrailStep {}
+
+impl Ord for TrailStep {
+    fn cmp(&self, other: &Self) -> Ordering {
+        other
+            .length
+            .total_cmp(&self.length)
+            .then_with(|| self.loc.cmp(&other.loc))
+    }
+}
+
+impl PartialOrd for TrailStep {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+#[derive(Eq, Hash, PartialEq, Ord, PartialOrd, Copy, Clone, Default, Debug)]
 pub struct GridLocation {
     r: usize,
     c: usize,
@@ -90,15 +190,23 @@ impl GridLocation {
     }
 }
 
+#[derive(Clone)]
 pub struct WorldGrid {
     grid: Vec<[WorldCell; GRID_HEIGHT]>,
     food_pheromones: Pheromones,
     home_pheromones: Pheromones,
     food_cell_locs: HashSet<GridLocation>,
+    food_freshness: HashMap<GridLocation, f32>, // from 1 for freshly placed food down to 0
+    home_cell_locs: HashSet<GridLocation>,
+    shadowed_cell_locs: HashSet<GridLocation>, // cells next to terrain, kept up to date as terrain is spawned
+    traffic: Vec<[u32; GRID_HEIGHT]>,          // how many ant steps ended in each cell
+    weathering: Arc<Vec<[f32; GRID_HEIGHT]>>, // per cell pheromone decay multipliers, never change so snapshots share them
     bounding_box: Rect,
     pub(crate) cell_width: f32,
     cell_height: f32,
     food_collected: u32,
+    terrain_deposit_penalty: f32,
+    deposit_jitter: f32,
 }
 
 impl WorldGrid {
@@ -117,13 +225,13 @@ impl WorldGrid {
         let cell_height = (screen_height) / GRID_HEIGHT as f32;
 
         // set rect sizes and locations for all cells
-        for c in 0..GRID_WIDTH {
-            for r in 0..GRID_HEIGHT {
+        for (c, column) in grid.iter_mut().enumerate() {
+            for (r, cell) in column.iter_mut().enumerate() {
                 let x = c as f32 * cell_width;
                 let y = r as f32 * cell_height;
 
-                grid[c][r].rect = Rect::new(x, y, cell_width, cell_height);
-                grid[c][r].loc = GridLocation { r, c };
+                cell.rect = Rect::new(x, y, cell_width, cell_height);
+                cell.loc = GridLocation { r, c };
             }
         }
 
@@ -136,6 +244,13 @@ impl WorldGrid {
             cell_height,
             food_collected: 0,
             food_cell_locs: HashSet::new(),
+            food_freshness: HashMap::new(),
+            home_cell_locs: home_locations.iter().copied().collect(),
+            shadowed_cell_locs: HashSet::new(),
+            traffic: vec![[0; GRID_HEIGHT]; GRID_WIDTH],
+            weathering: Arc::new(weathering_map(WEATHERING_STRENGTH, rand::random())),
+            terrain_deposit_penalty: TERRAIN_DEPOSIT_PENALTY,
+            deposit_jitter: DEPOSIT_JITTER,
         };
 
         // spawn home pheromones
@@ -152,13 +267,33 @@ impl WorldGrid {
         grid
     }
 
-    pub fn draw(&self, ants: &[Ant]) {
-        for ph in self.food_pheromones.entries.values() {
-            ph.draw();
+    pub fn draw(&self, ants: &[AntSnapshot]) {
+        if COMBINED_PHEROMONE_FIELD {
+            for (loc, intensity) in self.combined_pheromone_intensities() {
+                let brightness = self.pheromone_brightness(loc);
+                let color = Color {
+                    r: COMBINED_PHEROMONE_COLOR.r * brightness,
+                    g: COMBINED_PHEROMONE_COLOR.g * brightness,
+                    b: COMBINED_PHEROMONE_COLOR.b * brightness,
+                    a: intensity.min(MAX_COMBINED_PHEROMONE_OPACITY),
+                };
+                self.grid[loc.c][loc.r].rect.draw_rectangle(color);
+            }
+        } else {
+            for (loc, ph) in &self.food_pheromones.entries {
+                ph.draw(self.pheromone_brightness(*loc));
+            }
+
+            for (loc, ph) in &self.home_pheromones.entries {
+                ph.draw(self.pheromone_brightness(*loc));
+            }
         }
 
-        for ph in self.home_pheromones.entries.values() {
-            ph.draw();
+        if FOOD_GLOW {
+            // glows go in their own pass so they stay behind every cell, not just their own
+            for loc in &self.food_cell_locs {
+                self.grid[loc.c][loc.r].draw_glow();
+            }
         }
 
         self.grid.iter().for_each(|row| {
@@ -172,18 +307,42 @@ impl WorldGrid {
             }
         });
 
+        if DEBUG && DRAW_HOME_GRADIENT {
+            self.draw_gradient(PheromoneType::Home);
+        }
+
         self.draw_ui(ants);
     }
 
-    fn draw_ui(&self, ants: &[Ant]) {
-        let fps = get_fps();
-        let food_remaining = self.food_cell_locs.iter().fold(0, |sum, loc| {
-            if let CellType::Food(remaining_amount) = self.grid[loc.c][loc.r].cell_type {
-                sum + remaining_amount
-            } else {
-                sum
+    /// Pheromones in the shadow of terrain are drawn darker when terrain shadows are on
+    fn pheromone_brightness(&self, loc: GridLocation) -> f32 {
+        if TERRAIN_SHADOWS && self.is_shadowed(loc) {
+            TERRAIN_SHADOW_BRIGHTNESS
+        } else {
+            1.
+        }
+    }
+
+    /// Draws arrows pointing up the pheromone gradient, on a coarser grid than the world grid
+    fn draw_gradient(&self, pheromone_type: PheromoneType) {
+        let arrow_length =
+            GRADIENT_ARROW_SPACING as f32 * self.cell_width.min(self.cell_height) / 2.;
+
+        for c in (0..GRID_WIDTH).step_by(GRADIENT_ARROW_SPACING) {
+            for r in (0..GRID_HEIGHT).step_by(GRADIENT_ARROW_SPACING) {
+                let loc = GridLocation { r, c };
+                let gradient = self.gradient_at(pheromone_type, loc);
+                if gradient != Vec2::ZERO {
+                    let origin = self.grid[c][r].rect.center();
+                    draw_arrow(origin, gradient, arrow_length, GRADIENT_ARROW_COLOR);
+                }
             }
-        });
+        }
+    }
+
+    fn draw_ui(&self, ants: &[AntSnapshot]) {
+        let fps = get_fps();
+        let food_remaining = self.food_remaining();
 
         let ants_with_food = ants
             .iter()
@@ -196,9 +355,19 @@ impl WorldGrid {
             // format!("Food collected: {}", self.food_collected),
             format!("Food remaining: {}", food_remaining),
             format!("Ants with food: {}", ants_with_food),
+            format!(
+                "Avg food pheromone: {:.2}",
+                self.food_pheromones.average_intensity()
+            ),
+            format!(
+                "Avg home pheromone: {:.2}",
+                self.home_pheromones.average_intensity()
+            ),
             "Controls:".to_string(),
             "LMB - Spawn food, RMB - Spawn terrain".to_string(),
             "R - Reset, Space - Pause, ESC - Quit".to_string(),
+            "L - Print trail loops, T - Print trail length from cursor".to_string(),
+            "F - Save pheromone field".to_string(),
         ];
 
         let mut y = INSTRUCTIONS_Y;
@@ -209,9 +378,86 @@ impl WorldGrid {
         }
     }
 
+    /// Saves a PNG overview of the run: a heatmap of where ants walked, with terrain, food and the nest drawn
+    /// on top. Live pheromones are left out since they only show recent activity.
+    pub fn render_summary_image(&self, path: impl AsRef<Path>) -> ImageResult<()> {
+        self.summary_image().save(path)
+    }
+
+    fn summary_image(&self) -> RgbImage {
+        let max_traffic = self.traffic.iter().flatten().copied().max().unwrap_or(0);
+
+        RgbImage::from_fn(
+            GRID_WIDTH as u32 * SUMMARY_IMAGE_CELL_SIZE,
+            GRID_HEIGHT as u32 * SUMMARY_IMAGE_CELL_SIZE,
+            |x, y| {
+                let c = (x / SUMMARY_IMAGE_CELL_SIZE) as usize;
+                let r = (y / SUMMARY_IMAGE_CELL_SIZE) as usize;
+
+                // log scale so that quiet paths still show up next to the busy ones near the nest
+                let traffic = self.traffic[c][r];
+                let heat = if traffic > 0 {
+                    (traffic as f32).ln_1p() / (max_traffic as f32).ln_1p()
+                } else {
+                    0.
+                };
+                let mut pixel = [
+                    TRAFFIC_HEATMAP_COLOR.r * heat,
+                    TRAFFIC_HEATMAP_COLOR.g * heat,
+                    TRAFFIC_HEATMAP_COLOR.b * heat,
+                ];
+
+                if let Some(color) = self.grid[c][r].color() {
+                    for (channel, value) in pixel.iter_mut().zip([color.r, color.g, color.b]) {
+                        *channel += (value - *channel) * color.a;
+                    }
+                }
+
+                Rgb(pixel.map(|channel| (channel * 255.).round() as u8))
+            },
+        )
+    }
+
+    /// Returns the combined food and home pheromone intensities as a grayscale image with a pixel per cell.
+    /// Uses a fixed scale so that frames taken at different times can be compared.
+    pub fn pheromone_heat_image(&self) -> GrayImage {
+        let mut image = GrayImage::new(GRID_WIDTH as u32, GRID_HEIGHT as u32);
+        for (loc, intensity) in self.combined_pheromone_intensities() {
+            let heat = (intensity / HEAT_FRAME_FULL_INTENSITY).min(1.);
+            image.put_pixel(
+                loc.c as u32,
+                loc.r as u32,
+                Luma([(heat * 255.).round() as u8]),
+            );
+        }
+        image
+    }
+
+    /// Returns how much food is left on the map, across all food cells
+    pub fn food_remaining(&self) -> u32 {
+        self.food_cell_locs.iter().fold(0, |sum, loc| {
+            if let CellType::Food(remaining_amount) = self.grid[loc.c][loc.r].cell_type {
+                sum + remaining_amount
+            } else {
+                sum
+            }
+        })
+    }
+
+    pub fn food_collected(&self) -> u32 {
+        self.food_collected
+    }
+
     pub fn tick(&mut self, dt: f32) {
-        self.food_pheromones.tick(dt);
-        self.home_pheromones.tick(dt);
+        for freshness in self.food_freshness.values_mut() {
+            *freshness *= (1. - dt * FOOD_FRESHNESS_DECAY_RATE).max(0.);
+        }
+
+        let weathering = &self.weathering;
+        self.food_pheromones
+            .tick(dt, |loc| weathering[loc.c][loc.r]);
+        self.home_pheromones
+            .tick(dt, |loc| weathering[loc.c][loc.r]);
     }
 
     pub fn bounding_box(&self) -> &Rect {
@@ -281,6 +527,16 @@ impl WorldGrid {
             .get_grid_location(pheromone.rect().center().x, pheromone.rect().center().y)
             .expect("Invalid location for pheromone");
 
+        // weaken trails that hug walls so ants prefer routes through open space
+        let pheromone = if !pheromone.locked_intensity()
+            && self.terrain_deposit_penalty > 0.
+            && self.is_shadowed(loc)
+        {
+            pheromone.with_intensity(pheromone.intensity() * (1. - self.terrain_deposit_penalty))
+        } else {
+            pheromone
+        };
+
         let pheromones = match pheromone.pheromone_type() {
             PheromoneType::Food => &mut self.food_pheromones,
             PheromoneType::Home => &mut self.home_pheromones,
@@ -299,8 +555,239 @@ impl WorldGrid {
         pheromones.entries.insert(loc, pheromone);
     }
 
+    /// Returns the fraction of non-terrain cells holding a detectable pheromone of the given type
+    pub fn coverage(&self, pheromone_type: PheromoneType) -> f32 {
+        self.share_of_open_cells(self.pheromones(pheromone_type).entries.len())
+    }
+
+    /// Like `coverage`, but only counts pheromones laid by ants, which decay can actually clear up
+    pub fn trail_coverage(&self, pheromone_type: PheromoneType) -> f32 {
+        let trail_cells = self
+            .pheromones(pheromone_type)
+            .entries
+            .values()
+            .filter(|ph| !ph.locked_intensity())
+            .count();
+        self.share_of_open_cells(trail_cells)
+    }
+
+    fn share_of_open_cells(&self, cells: usize) -> f32 {
+        let open_cells = self
+            .grid
+            .iter()
+            .flatten()
+            .filter(|cell| cell.cell_type != CellType::Terrain)
+            .count();
+        if open_cells == 0 {
+            return 0.;
+        }
+
+        cells as f32 / open_cells as f32
+    }
+
+    /// Returns the sum of food and home pheromone intensities for every cell holding either
+    pub fn combined_pheromone_intensities(&self) -> HashMap<GridLocation, f32> {
+        let mut combined = HashMap::new();
+        for pheromones in [&self.food_pheromones, &self.home_pheromones] {
+            for (loc, ph) in &pheromones.entries {
+                *combined.entry(*loc).or_default() += ph.intensity();
+            }
+        }
+        combined
+    }
+
+    /// Returns the direction of steepest pheromone intensity increase at the location, in screen
+    /// coordinates. Cells without pheromones count as zero intensity.
+    pub fn gradient_at(&self, pheromone_type: PheromoneType, loc: GridLocation) -> Vec2 {
+        let pheromones = self.pheromones(pheromone_type);
+        let intensity_at = |r: i32, c: i32| {
+            if r < 0 || r >= GRID_HEIGHT as i32 || c < 0 || c >= GRID_WIDTH as i32 {
+                return 0.;
+            }
+            pheromones
+                .entries
+                .get(&GridLocation::new(r as usize, c as usize))
+                .map_or(0., |ph| ph.intensity())
+        };
+
+        let (r, c) = (loc.r as i32, loc.c as i32);
+        // central differences
+        Vec2::new(
+            intensity_at(r, c + 1) - intensity_at(r, c - 1),
+            intensity_at(r + 1, c) - intensity_at(r - 1, c),
+        )
+    }
+
+    /// Follows trails of the given pheromone type, always stepping onto the strongest neighbouring
+    /// pheromone (without turning straight back), and returns the cells of every closed loop found.
+    /// Locked pheromones (food and home cells) end a trail rather than continue it.
+    pub fn find_trail_loops(
+        &self,
+        pheromone_type: PheromoneType,
+        min_intensity: f32,
+        min_loop_cells: usize,
+    ) -> Vec<Vec<GridLocation>> {
+        let pheromones = self.pheromones(pheromone_type);
+        let mut loops = Vec::new();
+        let mut found_loops = HashSet::new();
+
+        for start in pheromones
+            .entries
+            .keys()
+            .filter(|loc| self.is_trail(pheromone_type, **loc, min_intensity))
+        {
+            let mut path = vec![*start];
+            let mut path_indices = HashMap::from([(*start, 0)]);
+            let mut previous = None;
+
+            loop {
+                let current = *path.last().unwrap();
+                let next = self.next_trail_cell(pheromone_type, current, min_intensity, |loc| {
+                    Some(*loc) != previous
+                });
+
+                let Some(next) = next else {
+                    break; // trail ends here
+                };
+
+                if let Some(&loop_start) = path_indices.get(&next) {
+                    let cells = path.split_off(loop_start);
+                    // very short loops are just the walk wobbling around inside a thick trail
+                    if cells.len() >= min_loop_cells {
+                        let mut key = cells.clone();
+                        key.sort();
+                        if found_loops.insert(key) {
+                            loops.push(cells);
+                        }
+                    }
+                    break;
+                }
+
+                path_indices.insert(next, path.len());
+                path.push(next);
+                previous = Some(current);
+            }
+        }
+
+        loops
+    }
+
+    /// Returns the length of the shortest trail from `source` to the edge of the nest, or `None` if no
+    /// trail gets there. Searches every branch, so dead ends along the way don't hide a longer route.
+    pub fn trail_length_to_home(
+        &self,
+        pheromone_type: PheromoneType,
+        source: GridLocation,
+        min_intensity: f32,
+    ) -> Option<f32> {
+        let mut lengths = HashMap::from([(source, 0.)]);
+        let mut frontier = BinaryHeap::from([TrailStep {
+            length: 0.,
+            loc: source,
+        }]);
+
+        while let Some(TrailStep { length, loc }) = frontier.pop() {
+            if self.grid[loc.c][loc.r].cell_type == CellType::Home {
+                return Some(length);
+            }
+            if lengths.get(&loc).is_some_and(|&shortest| length > shortest) {
+                // already reached this cell along a shorter route
+                continue;
+            }
+
+            let center = self.get_rect_from_loc(loc).center();
+            for next in self.neighbors(loc) {
+                if self.grid[next.c][next.r].cell_type != CellType::Home
+                    && !self.is_trail(pheromone_type, next, min_intensity)
+                {
+                    continue;
+                }
+                let next_length = length + center.distance(self.get_rect_from_loc(next).center());
+                if lengths
+                    .get(&next)
+                    .is_none_or(|&shortest| next_length < shortest)
+                {
+                    lengths.insert(next, next_length);
+                    frontier.push(TrailStep {
+                        length: next_length,
+                        loc: next,
+                    });
+                }
+            }
+        }
+
+        None
+    }
+
+    /// Returns the neighbouring trail cell with the strongest pheromone out of the allowed ones
+    fn next_trail_cell(
+        &self,
+        pheromone_type: PheromoneType,
+        loc: GridLocation,
+        min_intensity: f32,
+        allowed: impl Fn(&GridLocation) -> bool,
+    ) -> Option<GridLocation> {
+        let pheromones = self.pheromones(pheromone_type);
+        self.neighbors(loc)
+            .filter(|n| allowed(n) && self.is_trail(pheromone_type, *n, min_intensity))
+            .max_by(|l1, l2| {
+                pheromones.entries[l1]
+                    .intensity()
+                    .total_cmp(&pheromones.entries[l2].intensity())
+            })
+    }
+
+    /// Trails are made of pheromones laid by ants, locked pheromones (food and home cells) don't count
+    fn is_trail(
+        &self,
+        pheromone_type: PheromoneType,
+        loc: GridLocation,
+        min_intensity: f32,
+    ) -> bool {
+        self.pheromones(pheromone_type)
+            .entries
+            .get(&loc)
+            .is_some_and(|ph| !ph.locked_intensity() && ph.intensity() >= min_intensity)
+    }
+
+    /// Returns the (up to) 8 locations surrounding the given one
+    fn neighbors(&self, loc: GridLocation) -> impl Iterator<Item = GridLocation> {
+        (-1..=1)
+            .flat_map(|dr| (-1..=1).map(move |dc| (dr, dc)))
+            .filter(|&(dr, dc)| dr != 0 || dc != 0)
+            .filter_map(move |(dr, dc)| {
+                let r = loc.r as i32 + dr;
+                let c = loc.c as i32 + dc;
+                if r < 0 || r >= GRID_HEIGHT as i32 || c < 0 || c >= GRID_WIDTH as i32 {
+                    return None;
+                }
+                Some(GridLocation::new(r as usize, c as usize))
+            })
+    }
+
+    /// Returns true if any of the 8 cells surrounding the location is terrain. Only used to keep the
+    /// shadowed cells up to date, everything else should go through `is_shadowed`.
+    fn is_next_to_terrain(&self, loc: GridLocation) -> bool {
+        self.neighbors(loc)
+            .any(|n| self.grid[n.c][n.r].cell_type == CellType::Terrain)
+    }
+
+    /// Returns true if the cell is next to terrain, using the adjacency precomputed as terrain is spawned
+    pub fn is_shadowed(&self, loc: GridLocation) -> bool {
+        self.shadowed_cell_locs.contains(&loc)
+    }
+
+    /// Returns true if the location is a home cell on the border of the nest
+    pub fn is_nest_edge(&self, loc: GridLocation) -> bool {
+        self.home_cell_locs.contains(&loc)
+            && self
+                .neighbors(loc)
+                .any(|n| self.grid[n.c][n.r].cell_type != CellType::Home)
+    }
+
     pub fn visit_cell(&mut self, loc: GridLocation, action: Option<AntActionTaken>) {
         let cell = self.grid[loc.c][loc.r];
+        self.traffic[loc.c][loc.r] += 1;
 
         if let Some(action) = action {
             match action {
@@ -314,6 +801,7 @@ impl WorldGrid {
                             self.grid[loc.c][loc.r].cell_type = CellType::Empty;
                             self.food_pheromones.entries.remove(&loc);
                             self.food_cell_locs.remove(&loc);
+                            self.food_freshness.remove(&loc);
                         }
                     }
                 }
@@ -335,7 +823,13 @@ impl WorldGrid {
         intensity: f32,
         locked_intensity: bool,
     ) -> Pheromone {
-        let rect = self.get_rect_from_loc(loc);
+        let mut rect = self.get_rect_from_loc(loc);
+        if self.deposit_jitter > 0. {
+            // nudge the pheromone off the cell center so trails look less regular, staying within the cell
+            // so that it's still found under the same location
+            rect.x += gen_range(-self.deposit_jitter, self.deposit_jitter) * self.cell_width / 2.;
+            rect.y += gen_range(-self.deposit_jitter, self.deposit_jitter) * self.cell_height / 2.;
+        }
 
         Pheromone::new(intensity, pheromone_type, rect, locked_intensity)
     }
@@ -367,11 +861,16 @@ impl WorldGrid {
             }
         }
 
-        for loc in locs {
+        for &loc in &locs {
             // clear existing pheromones
             self.food_pheromones.entries.remove(&loc);
             self.home_pheromones.entries.remove(&loc);
 
+            if cell_type != CellType::Home {
+                self.home_cell_locs.remove(&loc);
+            }
+            self.food_freshness.remove(&loc);
+
             self.grid[loc.c][loc.r] = WorldCell {
                 cell_type,
                 rect: self.get_rect_from_loc(loc),
@@ -381,6 +880,7 @@ impl WorldGrid {
             if let CellType::Food(_) = cell_type {
                 // if spawning food, make sure it's tracked at the grid level and has pheromones attached to it
                 self.food_cell_locs.insert(loc);
+                self.food_freshness.insert(loc, 1.);
 
                 let rect = self.get_rect_from_loc(loc);
 
@@ -390,6 +890,19 @@ impl WorldGrid {
                 );
             }
         }
+
+        // only cells around the spawned ones can have gained or lost a terrain neighbor
+        let affected_locs: HashSet<GridLocation> = locs
+            .iter()
+            .flat_map(|loc| self.neighbors(*loc).chain(std::iter::once(*loc)))
+            .collect();
+        for loc in affected_locs {
+            if self.is_next_to_terrain(loc) {
+                self.shadowed_cell_locs.insert(loc);
+            } else {
+                self.shadowed_cell_locs.remove(&loc);
+            }
+        }
     }
 
     pub fn get_cell_for_coords(&self, x: f32, y: f32) -> Option<&WorldCell> {
@@ -401,6 +914,154 @@ impl WorldGrid {
         &self.grid[loc.c][loc.r]
     }
 
+    /// Writes the intensities of ant-laid pheromones as CSV, one section per pheromone type. Each section
+    /// starts with a `food` or `home` line followed by a row of comma separated intensities for every grid
+    /// row. Locked pheromones (eg on food or home cells) are written as 0 since they come from the cells.
+    pub fn write_pheromone_field(&self, out: &mut impl Write) -> io::Result<()> {
+        for (name, pheromones) in [
+            ("food", &self.food_pheromones),
+            ("home", &self.home_pheromones),
+        ] {
+            writeln!(out, "{}", name)?;
+            for r in 0..GRID_HEIGHT {
+                let row: Vec<String> = (0..GRID_WIDTH)
+                    .map(|c| match pheromones.entries.get(&GridLocation { r, c }) {
+                        Some(ph) if !ph.locked_intensity() => ph.intensity().to_string(),
+                        _ => "0".to_string(),
+                    })
+                    .collect();
+                writeln!(out, "{}", row.join(","))?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Populates the pheromone maps from a field in the format produced by `write_pheromone_field`.
+    /// Either section may be left out. Cells with locked pheromones keep them. Nothing is loaded unless
+    /// the whole field is valid.
+    pub fn read_pheromone_field(&mut self, input: &str) -> io::Result<()> {
+        let invalid = |msg: String| io::Error::new(io::ErrorKind::InvalidData, msg);
+
+        let mut sections: Vec<(PheromoneType, Vec<Vec<f32>>)> = Vec::new();
+        for (i, line) in input.lines().enumerate() {
+            let line = line.trim();
+            match line {
+                "" => continue,
+                "food" => sections.push((PheromoneType::Food, Vec::new())),
+                "home" => sections.push((PheromoneType::Home, Vec::new())),
+                _ => {
+                    let Some((_, rows)) = sections.last_mut() else {
+                        return Err(invalid(format!(
+                            "line {}: expected a food or home section",
+                            i + 1
+                        )));
+                    };
+                    let row = line
+                        .split(',')
+                        .map(|value| match value.trim().parse::<f32>() {
+                            Ok(intensity) if intensity.is_finite() && intensity >= 0. => {
+                                Ok(intensity)
+                            }
+                            _ => Err(invalid(format!(
+                                "line {}: invalid intensity {:?}",
+                                i + 1,
+                                value
+                            ))),
+                        })
+                        .collect::<io::Result<Vec<f32>>>()?;
+                    if row.len() != GRID_WIDTH {
+                        return Err(invalid(format!(
+                            "line {}: expected {} columns, found {}",
+                            i + 1,
+                            GRID_WIDTH,
+                            row.len()
+                        )));
+                    }
+                    rows.push(row);
+                }
+            }
+        }
+
+        for (_, rows) in &sections {
+            if rows.len() != GRID_HEIGHT {
+                return Err(invalid(format!(
+                    "expected {} rows per section, found {}",
+                    GRID_HEIGHT,
+                    rows.len()
+                )));
+            }
+        }
+
+        for (pheromone_type, rows) in sections {
+            for (r, row) in rows.into_iter().enumerate() {
+                for (c, intensity) in row.into_iter().enumerate() {
+                    let loc = GridLocation { r, c };
+                    let ph = self.create_pheromone_for_loc(loc, pheromone_type, intensity, false);
+                    let pheromones = match pheromone_type {
+                        PheromoneType::Food => &mut self.food_pheromones,
+                        PheromoneType::Home => &mut self.home_pheromones,
+                    };
+                    if pheromones
+                        .entries
+                        .get(&loc)
+                        .is_some_and(|ph| ph.locked_intensity())
+                    {
+                        continue;
+                    }
+                    if intensity > 0. {
+                        pheromones.entries.insert(loc, ph);
+                    } else {
+                        pheromones.entries.remove(&loc);
+                    }
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Returns how fresh the food at the location is, from 1 for just placed food down to 0. Locations
+    /// without food are 0.
+    pub fn food_freshness(&self, loc: GridLocation) -> f32 {
+        self.food_freshness.get(&loc).copied().unwrap_or(0.)
+    }
+
+    pub fn decay_rate(&self) -> f32 {
+        self.food_pheromones.decay_rate()
+    }
+
+    /// Changes how quickly both food and home pheromones decay
+    pub fn set_decay_rate(&mut self, decay_rate: f32) {
+        self.food_pheromones.set_decay_rate(decay_rate);
+        self.home_pheromones.set_decay_rate(decay_rate);
+    }
+
+    /// Returns the straight line distance from the point to the closest home cell, if there are any
+    pub fn distance_to_nest(&self, point: Vec2) -> Option<f32> {
+        self.home_cell_locs
+            .iter()
+            .map(|loc| self.get_rect_from_loc(*loc).center().distance(point))
+            .min_by(|d1, d2| d1.total_cmp(d2))
+    }
+
+    /// Returns the center of mass of the home cells, if there are any
+    pub fn nest_centroid(&self) -> Option<Vec2> {
+        if self.home_cell_locs.is_empty() {
+            return None;
+        }
+
+        let total: Vec2 = self
+            .home_cell_locs
+            .iter()
+            .map(|loc| self.get_rect_from_loc(*loc).center())
+            .sum();
+        Some(total / self.home_cell_locs.len() as f32)
+    }
+
+    pub fn home_locations(&self) -> &HashSet<GridLocation> {
+        &self.home_cell_locs
+    }
+
     pub fn pheromones(&self, pheromone_type: PheromoneType) -> &Pheromones {
         match pheromone_type {
             PheromoneType::Food => &self.food_pheromones,
@@ -408,3 +1069,305 @@ impl WorldGrid {
         }
     }
 }
+
+#[test]
+fn test_food_glow_rect() {
+    let cell_rect = Rect::new(10., 20., 4., 2.);
+
+    let full_glow = food_glow_rect(&cell_rect, FOOD_CONSUMPTION_LIMIT);
+    assert_eq!(full_glow.center(), cell_rect.center());
+    assert_eq!(full_glow.w, 4. + 2. * 4. * FOOD_GLOW_MAX_SPREAD);
+    assert_eq!(full_glow.h, 2. + 2. * 2. * FOOD_GLOW_MAX_SPREAD);
+
+    // glow shrinks with the remaining amount
+    let half_glow = food_glow_rect(&cell_rect, FOOD_CONSUMPTION_LIMIT / 2);
+    assert!(half_glow.w < full_glow.w && half_glow.w > cell_rect.w);
+    assert!(half_glow.h < full_glow.h && half_glow.h > cell_rect.h);
+
+    // depleted food has no glow beyond the cell itself
+    assert_eq!(food_glow_rect(&cell_rect, 0), cell_rect);
+}
+
+#[test]
+fn test_terrain_deposit_penalty() {
+    let mut grid = WorldGrid::new(&[], 800., 600.);
+    grid.terrain_deposit_penalty = 0.25;
+
+    // 5x5 block of terrain centered on row 50, column 50
+    let terrain_center = grid.get_rect_from_loc(GridLocation::new(50, 50)).center();
+    grid.spawn_cells(terrain_center.x, terrain_center.y, CellType::Terrain);
+
+    let next_to_terrain = GridLocation::new(50, 53);
+    let open_space = GridLocation::new(50, 60);
+    assert!(grid.is_shadowed(next_to_terrain));
+    assert!(!grid.is_shadowed(open_space));
+
+    for loc in [next_to_terrain, open_space] {
+        let ph = grid.create_pheromone_for_loc(loc, PheromoneType::Food, 1., false);
+        grid.deposit_pheromone(ph);
+    }
+
+    let intensity_at = |loc| grid.food_pheromones.entries[&loc].intensity();
+    assert_eq!(intensity_at(next_to_terrain), 0.75);
+    assert_eq!(intensity_at(open_space), 1.);
+}
+
+#[test]
+fn test_find_trail_loops() {
+    let mut grid = WorldGrid::new(&[], 800., 600.);
+
+    // square ring of cells with rows and columns 40 to 50, intensity rising as we go around it
+    let mut ring = Vec::new();
+    ring.extend((40..50).map(|c| GridLocation::new(40, c)));
+    ring.extend((40..50).map(|r| GridLocation::new(r, 50)));
+    ring.extend((41..=50).rev().map(|c| GridLocation::new(50, c)));
+    ring.extend((41..=50).rev().map(|r| GridLocation::new(r, 40)));
+    for (i, loc) in ring.iter().enumerate() {
+        let ph =
+            grid.create_pheromone_for_loc(*loc, PheromoneType::Food, 2. + i as f32 / 10., false);
+        grid.deposit_pheromone(ph);
+    }
+
+    // straight trail that leads nowhere
+    for c in 100..150 {
+        let ph = grid.create_pheromone_for_loc(
+            GridLocation::new(100, c),
+            PheromoneType::Food,
+            2.,
+            false,
+        );
+        grid.deposit_pheromone(ph);
+    }
+
+    let loops = grid.find_trail_loops(PheromoneType::Food, 1., 8);
+    assert!(!loops.is_empty());
+    for trail_loop in loops {
+        assert!(trail_loop.len() >= 30);
+        assert!(trail_loop.iter().all(|loc| ring.contains(loc)));
+    }
+}
+
+#[test]
+fn test_combined_pheromone_intensities() {
+    let mut grid = WorldGrid::new(&[], 800., 600.);
+    let both = GridLocation::new(10, 10);
+    let food_only = GridLocation::new(20, 20);
+    let home_only = GridLocation::new(30, 30);
+
+    for (loc, pheromone_type, intensity) in [
+        (both, PheromoneType::Food, 1.),
+        (both, PheromoneType::Home, 2.),
+        (food_only, PheromoneType::Food, 0.5),
+        (home_only, PheromoneType::Home, 0.25),
+    ] {
+        let ph = grid.create_pheromone_for_loc(loc, pheromone_type, intensity, false);
+        grid.deposit_pheromone(ph);
+    }
+
+    let combined = grid.combined_pheromone_intensities();
+    assert_eq!(combined.len(), 3);
+    assert_eq!(combined[&both], 3.);
+    assert_eq!(combined[&food_only], 0.5);
+    assert_eq!(combined[&home_only], 0.25);
+}
+
+#[test]
+fn test_trail_length_to_home() {
+    let mut grid = WorldGrid::new(&crate::simulation::nest_locations(), 800., 600.);
+    let source = GridLocation::new(75, 120);
+
+    // no trail yet
+    assert_eq!(
+        grid.trail_length_to_home(PheromoneType::Food, source, 1.),
+        None
+    );
+
+    // straight trail from the source to the edge of the nest, strongest next to the source
+    for c in 105..120 {
+        let intensity = 1. + (c - 105) as f32;
+        let ph = grid.create_pheromone_for_loc(
+            GridLocation::new(75, c),
+            PheromoneType::Food,
+            intensity,
+            false,
+        );
+        grid.deposit_pheromone(ph);
+    }
+
+    // 16 cells from the source to the first home cell, each 4 units wide
+    let length = grid
+        .trail_length_to_home(PheromoneType::Food, source, 1.)
+        .expect("trail reaches the nest");
+    assert!((length - 64.).abs() < 0.001);
+
+    // too faint to follow all the way home
+    assert_eq!(
+        grid.trail_length_to_home(PheromoneType::Food, source, 2.),
+        None
+    );
+}
+
+#[test]
+fn test_trail_length_to_home_across_wide_trail() {
+    let mut grid = WorldGrid::new(&crate::simulation::nest_locations(), 800., 600.);
+    let source = GridLocation::new(75, 130);
+
+    // 3 cells wide trail, strongest next to the source
+    for r in 74..77 {
+        for c in 105..130 {
+            let ph = grid.create_pheromone_for_loc(
+                GridLocation::new(r, c),
+                PheromoneType::Food,
+                1. + (c - 105) as f32,
+                false,
+            );
+            grid.deposit_pheromone(ph);
+        }
+    }
+
+    // straight down the middle, 26 cells from the source to the first home cell
+    let length = grid
+        .trail_length_to_home(PheromoneType::Food, source, 1.)
+        .expect("trail reaches the nest");
+    assert!((length - 104.).abs() < 0.001);
+
+    let straight_line = grid
+        .distance_to_nest(grid.get_rect_from_loc(source).center())
+        .unwrap();
+    assert!((straight_line - 104.).abs() < 0.001);
+}
+
+#[test]
+fn test_trail_length_to_home_past_dead_end() {
+    let mut grid = WorldGrid::new(&crate::simulation::nest_locations(), 800., 600.);
+    let source = GridLocation::new(75, 130);
+
+    // a stub heading straight for the nest that stops short, and a detour that goes all the way
+    let stub = (112..130).map(|c| GridLocation::new(75, c));
+    let detour = (60..75)
+        .map(|r| GridLocation::new(r, 130))
+        .chain((100..130).map(|c| GridLocation::new(60, c)))
+        .chain((61..70).map(|r| GridLocation::new(r, 100)));
+    for loc in stub.chain(detour) {
+        let ph = grid.create_pheromone_for_loc(loc, PheromoneType::Food, 1., false);
+        grid.deposit_pheromone(ph);
+    }
+
+    // along the detour, cutting its two corners diagonally
+    let length = grid
+        .trail_length_to_home(PheromoneType::Food, source, 1.)
+        .expect("the detour reaches the nest");
+    assert!((length - (51. * 4. + 2. * 32f32.sqrt())).abs() < 0.001);
+}
+
+#[test]
+fn test_terrain_shadow_adjacency() {
+    let mut grid = WorldGrid::new(&[], 800., 600.);
+
+    // 5x5 block of terrain centered on row 50, column 50
+    let terrain_center = grid.get_rect_from_loc(GridLocation::new(50, 50)).center();
+    grid.spawn_cells(terrain_center.x, terrain_center.y, CellType::Terrain);
+
+    assert!(grid.is_shadowed(GridLocation::new(50, 53)));
+    assert!(grid.is_shadowed(GridLocation::new(47, 47)));
+    assert!(!grid.is_shadowed(GridLocation::new(50, 54)));
+
+    // covering the terrain up removes its shadow
+    grid.spawn_cells(terrain_center.x, terrain_center.y, CellType::Food(1));
+    assert!(!grid.is_shadowed(GridLocation::new(50, 53)));
+}
+
+#[test]
+fn test_weathering_slows_decay_in_low_decay_regions() {
+    let mut grid = WorldGrid::new(&[], 800., 600.);
+
+    let weathering = weathering_map(0.8, 7);
+    assert!(
+        weathering
+            .iter()
+            .flatten()
+            .all(|multiplier| (0.2..=1.8).contains(multiplier))
+    );
+
+    let mut locs: Vec<GridLocation> = (0..GRID_WIDTH)
+        .flat_map(|c| (0..GRID_HEIGHT).map(move |r| GridLocation { r, c }))
+        .collect();
+    locs.sort_by(|l1, l2| weathering[l1.c][l1.r].total_cmp(&weathering[l2.c][l2.r]));
+    let (low_decay, high_decay) = (locs[0], locs[locs.len() - 1]);
+    assert!(weathering[low_decay.c][low_decay.r] < weathering[high_decay.c][high_decay.r]);
+    grid.weathering = Arc::new(weathering);
+
+    for loc in [low_decay, high_decay] {
+        let ph = grid.create_pheromone_for_loc(loc, PheromoneType::Food, 1., false);
+        grid.deposit_pheromone(ph);
+    }
+    for _ in 0..10 {
+        grid.tick(0.1);
+    }
+
+    let intensity_at = |loc| grid.food_pheromones.entries[&loc].intensity();
+    assert!(intensity_at(low_decay) > intensity_at(high_decay));
+}
+
+#[test]
+fn test_summary_image() {
+    let mut grid = WorldGrid::new(&[GridLocation::new(75, 100)], 800., 600.);
+    let busy = GridLocation::new(10, 20);
+    let quiet = GridLocation::new(10, 21);
+    for _ in 0..5 {
+        grid.visit_cell(busy, None);
+    }
+    grid.visit_cell(quiet, None);
+
+    let image = grid.summary_image();
+    assert_eq!(image.width(), GRID_WIDTH as u32 * SUMMARY_IMAGE_CELL_SIZE);
+    assert_eq!(image.height(), GRID_HEIGHT as u32 * SUMMARY_IMAGE_CELL_SIZE);
+
+    let pixel_at = |loc: GridLocation| {
+        *image.get_pixel(
+            loc.c as u32 * SUMMARY_IMAGE_CELL_SIZE,
+            loc.r as u32 * SUMMARY_IMAGE_CELL_SIZE,
+        )
+    };
+    let brightness = |loc| {
+        pixel_at(loc)
+            .0
+            .iter()
+            .map(|channel| *channel as u32)
+            .sum::<u32>()
+    };
+    assert!(brightness(busy) > brightness(quiet));
+    assert!(brightness(quiet) > 0);
+    assert_eq!(brightness(GridLocation::new(100, 150)), 0);
+    assert!(brightness(GridLocation::new(75, 100)) > 0); // the nest is drawn too
+}
+
+#[test]
+fn test_deposit_jitter() {
+    let mut grid = WorldGrid::new(&[], 800., 600.);
+    let loc = GridLocation::new(20, 30);
+    let cell_center = grid.get_rect_from_loc(loc).center();
+
+    let ph = grid.create_pheromone_for_loc(loc, PheromoneType::Food, 1., false);
+    assert_eq!(ph.rect().center(), cell_center);
+
+    grid.deposit_jitter = 0.9;
+    let pheromones: Vec<Pheromone> = (0..100)
+        .map(|_| grid.create_pheromone_for_loc(loc, PheromoneType::Food, 1., false))
+        .collect();
+    assert!(
+        pheromones
+            .iter()
+            .any(|ph| ph.rect().center() != cell_center)
+    );
+    for ph in &pheromones {
+        assert_eq!(grid.get_grid_location_for_rect(ph.rect()), Some(loc));
+    }
+
+    // deposits still end up in the same cell
+    for ph in pheromones {
+        grid.deposit_pheromone(ph);
+    }
+    assert_eq!(grid.food_pheromones.entries.len(), 1);
+    assert!(grid.food_pheromones.entries.contains_key(&loc));
+}
@@ -1,29 +1,207 @@
 use macroquad::prelude::*;
-use rayon::prelude::*;
 
-use crate::ant::{Ant, AntActionTaken};
-use crate::grid::{
-    CellType, FOOD_CONSUMPTION_LIMIT, GRID_HEIGHT, GRID_WIDTH, GridLocation, WorldGrid,
-};
-use crate::pheromone::Pheromone;
+use crate::ant::{AntSnapshot, AntSprite, draw_intent_lines};
+use crate::grid::{CellType, FOOD_CONSUMPTION_LIMIT, WorldGrid};
+use crate::simulation::{BackgroundSimulation, Simulation, SimulationCommand, SimulationSnapshot};
 
 mod ant;
 mod grid;
 mod pheromone;
+mod simulation;
 mod util;
 
 const DEBUG: bool = false;
 const ANT_COUNT: usize = 1_000;
+const BACKGROUND_SIMULATION: bool = false; // step the simulation on its own thread instead of the render loop
+// Render the world at a fixed resolution and scale it to the window instead of drawing it directly.
+// Render cost no longer depends on the window size and output is pixel-stable (handy for recordings),
+// at the price of blocky or blurry visuals when the window is much larger than the render target.
+const RENDER_RESOLUTION: Option<(u32, u32)> = None;
+const MAX_SIMULATED_TIME: Option<f32> = None; // simulated seconds after which the app exits, can be set with --max-sim-time
+const STOP_WHEN_FOOD_CLEARED: bool = false; // end the run once all food is collected, can be set with --until-food-cleared
+const HEAT_FRAME_INTERVAL: u64 = 10; // ticks between pheromone heat frames, can be set with --heat-frame-interval
+const HEADLESS_WORLD_SIZE: (f32, f32) = (800., 600.); // world size when running without a window
+const HEADLESS_STEP: f32 = 1. / 60.;
+const PHEROMONE_FIELD_DUMP_PATH: &str = "pheromone_field.csv"; // where the F key saves the pheromone field
 
-#[macroquad::main("Ants")]
-async fn main() {
+#[derive(Clone)]
+struct Options {
+    headless: bool,
+    max_simulated_time: Option<f32>,
+    pheromone_field: Option<String>, // field file to seed the pheromone maps with
+    summary_image: Option<String>,   // where to save an overview image of the run when it ends
+    until_food_cleared: bool,
+    food_spawns: Vec<(f32, f32)>, // where to place food before the run starts
+    heat_frames: Option<String>,  // directory to save pheromone heat frames to
+    heat_frame_interval: u64,
+}
+
+impl Options {
+    fn from_args(mut args: impl Iterator<Item = String>) -> Self {
+        let mut options = Options {
+            headless: false,
+            max_simulated_time: MAX_SIMULATED_TIME,
+            pheromone_field: None,
+            summary_image: None,
+            until_food_cleared: STOP_WHEN_FOOD_CLEARED,
+            food_spawns: Vec::new(),
+            heat_frames: None,
+            heat_frame_interval: HEAT_FRAME_INTERVAL,
+        };
+
+        while let Some(arg) = args.next() {
+            match arg.as_str() {
+                "--headless" => options.headless = true,
+                "--max-sim-time" => {
+                    let seconds = args.next().and_then(|value| value.parse().ok());
+                    if seconds.is_none() {
+                        exit_with_usage("--max-sim-time expects a number of seconds");
+                    }
+                    options.max_simulated_time = seconds;
+                }
+                "--pheromone-field" => {
+                    let path = args.next();
+                    if path.is_none() {
+                        exit_with_usage("--pheromone-field expects a file path");
+                    }
+                    options.pheromone_field = path;
+                }
+                "--summary-image" => {
+                    let path = args.next();
+                    if path.is_none() {
+                        exit_with_usage("--summary-image expects a file path");
+                    }
+                    options.summary_image = path;
+                }
+                "--until-food-cleared" => options.until_food_cleared = true,
+                "--heat-frames" => {
+                    let directory = args.next();
+                    if directory.is_none() {
+                        exit_with_usage("--heat-frames expects a directory");
+                    }
+                    options.heat_frames = directory;
+                }
+                "--heat-frame-interval" => match args.next().and_then(|value| value.parse().ok()) {
+                    Some(ticks) if ticks > 0 => options.heat_frame_interval = ticks,
+                    _ => exit_with_usage("--heat-frame-interval expects a number of ticks"),
+                },
+                "--spawn-food" => {
+                    let point = args.next().and_then(|value| {
+                        let (x, y) = value.split_once(',')?;
+                        Some((x.trim().parse().ok()?, y.trim().parse().ok()?))
+                    });
+                    match point {
+                        Some(point) => options.food_spawns.push(point),
+                        None => exit_with_usage("--spawn-food expects a position like 100,200"),
+                    }
+                }
+                _ => exit_with_usage(&format!("unknown argument {}", arg)),
+            }
+        }
+
+        if options.headless && options.max_simulated_time.is_none() {
+            exit_with_usage("--headless requires --max-sim-time");
+        }
+
+        options
+    }
+
+    /// Creates a simulation, seeded with the pheromone field file if one was given
+    fn new_simulation(&self, width: f32, height: f32) -> Simulation {
+        let mut simulation = Simulation::new(width, height);
+        for (x, y) in &self.food_spawns {
+            simulation.spawn_cells(*x, *y, CellType::Food(FOOD_CONSUMPTION_LIMIT));
+        }
+        if let Some(directory) = &self.heat_frames {
+            if let Err(err) = simulation.export_heat_frames(directory, self.heat_frame_interval) {
+                eprintln!("failed to set up heat frames in {}: {}", directory, err);
+                std::process::exit(1);
+            }
+        }
+        if let Some(path) = &self.pheromone_field {
+            if let Err(err) = simulation.load_pheromone_field(path) {
+                eprintln!("failed to load pheromone field from {}: {}", path, err);
+                std::process::exit(1);
+            }
+        }
+        simulation
+    }
+
+    /// Saves the summary image of the finished run, if one was asked for
+    fn save_summary_image(&self, render: impl FnOnce(&str) -> image::ImageResult<()>) {
+        if let Some(path) = &self.summary_image {
+            match render(path) {
+                Ok(()) => println!("Saved summary image to {}", path),
+                Err(err) => eprintln!("failed to save summary image to {}: {}", path, err),
+            }
+        }
+    }
+}
+
+/// The simulation shown by the window, either stepped by the render loop or on its own thread
+enum WindowedSimulation {
+    Foreground(Simulation),
+    Background(BackgroundSimulation, SimulationSnapshot),
+}
+
+fn exit_with_usage(error: &str) -> ! {
+    eprintln!("{}", error);
+    eprintln!(
+        "usage: ants_v2 [--headless] [--max-sim-time <seconds>] [--pheromone-field <path>] \
+         [--summary-image <path>] [--until-food-cleared] [--spawn-food <x>,<y>]... \
+         [--heat-frames <directory>] [--heat-frame-interval <ticks>]"
+    );
+    std::process::exit(2)
+}
+
+fn main() {
+    let options = Options::from_args(std::env::args().skip(1));
+
+    if options.headless {
+        let (width, height) = HEADLESS_WORLD_SIZE;
+        let max_simulated_time = options
+            .max_simulated_time
+            .expect("headless runs always have a time limit");
+        let mut simulation = options.new_simulation(width, height);
+        let summary = simulation.run_for(
+            HEADLESS_STEP,
+            max_simulated_time,
+            options.until_food_cleared,
+        );
+        println!("{}", summary);
+        options.save_summary_image(|path| simulation.render_summary_image(path));
+    } else {
+        macroquad::Window::new("Ants", run_windowed(options));
+    }
+}
+
+async fn run_windowed(options: Options) {
     let world_bounding_box = Rect::new(0., 0., screen_width(), screen_height());
 
     let ant_tileset = load_texture("assets/ant.png").await.unwrap();
 
-    let (mut ants, mut paused, mut grid) = init(&ant_tileset);
+    let sw = screen_width();
+    let sh = screen_height();
+
+    let mut paused = false;
+    let mut simulation = if BACKGROUND_SIMULATION {
+        let options = options.clone();
+        let background = BackgroundSimulation::spawn(move || options.new_simulation(sw, sh));
+        let snapshot = background.wait_for_snapshot();
+        WindowedSimulation::Background(background, snapshot)
+    } else {
+        WindowedSimulation::Foreground(options.new_simulation(sw, sh))
+    };
+    let mut ant_sprites: Vec<AntSprite> = Vec::new();
+    let world_render_target = RENDER_RESOLUTION.map(|(w, h)| {
+        let target = render_target(w, h);
+        target.texture.set_filter(FilterMode::Nearest);
+        target
+    });
 
     loop {
+        let mut commands = Vec::new();
+
         let keys_pressed = get_keys_pressed();
         if keys_pressed.contains(&KeyCode::Escape) {
             // quit
@@ -33,40 +211,105 @@ async fn main() {
         if keys_pressed.contains(&KeyCode::Space) {
             // pause
             paused = !paused;
+            commands.push(SimulationCommand::SetPaused(paused));
         }
 
         if keys_pressed.contains(&KeyCode::R) {
             // reset
-            (ants, paused, grid) = init(&ant_tileset);
+            paused = false;
+            commands.push(SimulationCommand::Reset);
+        }
+
+        if keys_pressed.contains(&KeyCode::L) {
+            // print food trail loops
+            commands.push(SimulationCommand::ReportTrailLoops);
+        }
+
+        if keys_pressed.contains(&KeyCode::T) {
+            // print the length of the trail from the cursor to the nest
+            let (x, y) = world_mouse_position(&world_bounding_box, &world_render_target);
+            commands.push(SimulationCommand::ReportTrailLength(x, y));
+        }
+
+        if keys_pressed.contains(&KeyCode::F) {
+            // save the pheromone field, eg to load it with --pheromone-field later
+            commands.push(SimulationCommand::DumpPheromoneField(
+                PHEROMONE_FIELD_DUMP_PATH.into(),
+            ));
         }
 
         if is_mouse_button_down(MouseButton::Left) {
-            let (x, y) = mouse_position();
-            grid.spawn_cells(x, y, CellType::Food(FOOD_CONSUMPTION_LIMIT))
+            let (x, y) = world_mouse_position(&world_bounding_box, &world_render_target);
+            commands.push(SimulationCommand::SpawnCells(
+                x,
+                y,
+                CellType::Food(FOOD_CONSUMPTION_LIMIT),
+            ));
         } else if is_mouse_button_down(MouseButton::Right) {
-            let (x, y) = mouse_position();
-            grid.spawn_cells(x, y, CellType::Terrain)
+            let (x, y) = world_mouse_position(&world_bounding_box, &world_render_target);
+            commands.push(SimulationCommand::SpawnCells(x, y, CellType::Terrain));
         }
 
-        if !paused {
-            let dt = get_frame_time();
-
-            grid.tick(dt);
-            let ant_state_updates: Vec<(GridLocation, Option<Pheromone>, Option<AntActionTaken>)> =
-                ants.par_iter_mut().map(|ant| ant.tick(&grid, dt)).collect();
+        let foreground_ants;
+        let (grid, ants, summary) = match &mut simulation {
+            WindowedSimulation::Background(background, snapshot) => {
+                commands.into_iter().for_each(|cmd| background.send(cmd));
+                if let Some(latest) = background.latest_snapshot() {
+                    *snapshot = latest;
+                }
+                (&snapshot.grid, snapshot.ants.as_slice(), snapshot.summary)
+            }
+            WindowedSimulation::Foreground(simulation) => {
+                for cmd in commands {
+                    match cmd {
+                        SimulationCommand::SpawnCells(x, y, cell_type) => {
+                            simulation.spawn_cells(x, y, cell_type)
+                        }
+                        SimulationCommand::SetPaused(_) => {}
+                        SimulationCommand::Reset => *simulation = options.new_simulation(sw, sh),
+                        SimulationCommand::ReportTrailLoops => simulation.report_trail_loops(),
+                        SimulationCommand::ReportTrailLength(x, y) => {
+                            simulation.report_trail_length(x, y)
+                        }
+                        SimulationCommand::DumpPheromoneField(path) => {
+                            simulation.report_pheromone_field_dump(&path)
+                        }
+                    }
+                }
 
-            ant_state_updates.into_iter().for_each(|(loc, ph, action)| {
-                // deposit pheromone on the grid if it was spawned by the ant
-                if let Some(pheromone) = ph {
-                    grid.deposit_pheromone(pheromone)
+                if !paused {
+                    simulation.step(get_frame_time());
                 }
-                grid.visit_cell(loc, action);
+
+                foreground_ants = simulation.ant_snapshots();
+                (
+                    simulation.grid(),
+                    foreground_ants.as_slice(),
+                    simulation.summary(),
+                )
+            }
+        };
+
+        if options
+            .max_simulated_time
+            .is_some_and(|max_time| summary.simulated_time >= max_time)
+            || (options.until_food_cleared && summary.food_cleared_at.is_some())
+        {
+            println!("{}", summary);
+            break;
+        }
+
+        if let Some(target) = &world_render_target {
+            // draw the world in its own coordinates into the fixed size target
+            set_camera(&Camera2D {
+                zoom: vec2(2. / world_bounding_box.w, 2. / world_bounding_box.h),
+                target: world_bounding_box.center(),
+                render_target: Some(target.clone()),
+                ..Default::default()
             });
         }
 
-        clear_background(BLACK);
-        grid.draw(&ants);
-        ants.iter_mut().for_each(|ant| ant.draw());
+        draw_world(grid, ants, &ant_tileset, &mut ant_sprites);
 
         if DEBUG {
             draw_line(
@@ -79,44 +322,64 @@ async fn main() {
             );
         }
 
+        if let Some(target) = &world_render_target {
+            // scale the rendered world to the window
+            set_default_camera();
+            clear_background(BLACK);
+            draw_texture_ex(
+                &target.texture,
+                0.,
+                0.,
+                WHITE,
+                DrawTextureParams {
+                    dest_size: Some(vec2(screen_width(), screen_height())),
+                    ..Default::default()
+                },
+            );
+        }
+
         next_frame().await
     }
-}
-
-fn init(ant_tileset: &Texture2D) -> (Vec<Ant>, bool, WorldGrid) {
-    let home_cells: usize = 10;
-    let home_start_row: usize = GRID_HEIGHT / 2 - home_cells / 2;
-    let home_start_col: usize = GRID_WIDTH / 2 - home_cells / 2;
 
-    let mut home_locs = Vec::new();
-    for r in home_start_row..home_start_row + home_cells {
-        for c in home_start_col..home_start_col + home_cells {
-            home_locs.push(GridLocation::new(r, c));
+    match &simulation {
+        WindowedSimulation::Background(_, snapshot) => {
+            options.save_summary_image(|path| snapshot.grid.render_summary_image(path))
+        }
+        WindowedSimulation::Foreground(simulation) => {
+            options.save_summary_image(|path| simulation.render_summary_image(path))
         }
     }
+}
 
-    let sw = screen_width();
-    let sh = screen_height();
+fn draw_world(
+    grid: &WorldGrid,
+    ants: &[AntSnapshot],
+    ant_tileset: &Texture2D,
+    ant_sprites: &mut Vec<AntSprite>,
+) {
+    // sprites only hold animation state, so they can be created lazily as ants show up
+    ant_sprites.resize_with(ants.len(), AntSprite::new);
 
-    let grid = WorldGrid::new(home_locs.as_slice(), sw, sh);
+    clear_background(BLACK);
+    grid.draw(ants);
+    ants.iter()
+        .zip(ant_sprites.iter_mut())
+        .for_each(|(ant, sprite)| sprite.draw(ant_tileset, ant));
+    draw_intent_lines(ants);
+}
 
-    let grid_center_loc = GridLocation::new(
-        home_start_row + home_cells / 2,
-        home_start_col + home_cells / 2,
-    );
-    let ant_spawn_point = grid.get_rect_from_loc(grid_center_loc);
-    let ants = std::iter::repeat_with(|| {
-        Ant::new(
-            ant_spawn_point.center().x,
-            ant_spawn_point.center().y,
-            ant_tileset,
-            &grid,
-        )
-    })
-    .take(ANT_COUNT)
-    .collect::<Vec<Ant>>();
-
-    let paused = false;
-
-    (ants, paused, grid)
+/// Returns the mouse position in world coordinates, which differ from the window's when the world is
+/// rendered at a fixed resolution and scaled to fit the window
+fn world_mouse_position(
+    world_bounding_box: &Rect,
+    world_render_target: &Option<RenderTarget>,
+) -> (f32, f32) {
+    let (x, y) = mouse_position();
+    match world_render_target {
+        Some(_) => (
+            x * world_bounding_box.w / screen_width(),
+            y * world_bounding_box.h / screen_height(),
+        ),
+        None => (x, y),
+    }
 }
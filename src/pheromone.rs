@@ -1,3 +1,4 @@
+use std::cmp::Ordering;
 use std::collections::{HashMap, HashSet};
 
 use macroquad::math::Rect;
@@ -15,6 +16,7 @@ const PHEROMONE_DECAY_RATE: f32 = 0.4;
 const PHEROMONE_DETECTION_MINIMUM: f32 = 0.01; // minimum pheromone health at which it is still detectable. Removed from the world below this value.
 const PHEROMONE_INTENSITY_MAX: f32 = 1000.;
 pub const SPECIAL_PHEROMONE_INTENSITY: f32 = 10000.;
+const PHEROMONE_FRESHNESS_WEIGHT: f32 = 0.; // how strongly ants prefer recently reinforced pheromones over stronger, older ones
 
 // Directions to check for pheromones. Something like the following:
 //   |/
@@ -41,6 +43,7 @@ pub struct Pheromone {
     rect: Rect,
     decayed: bool,
     locked_intensity: bool,
+    age: f32, // seconds since the pheromone was deposited or last reinforced
 }
 
 impl Pheromone {
@@ -56,9 +59,17 @@ impl Pheromone {
             rect,
             decayed: false,
             locked_intensity,
+            age: 0.,
         }
     }
-    pub fn draw(&self) {
+
+    /// Returns a copy of the pheromone with a different intensity
+    pub fn with_intensity(self, intensity: f32) -> Self {
+        Self { intensity, ..self }
+    }
+
+    /// Draws the pheromone, with `brightness` scaling its color, eg to shade it
+    pub fn draw(&self, brightness: f32) {
         // pheromone opacity depends on its intensity level
         let color = match self.pheromone_type {
             PheromoneType::Food => Color {
@@ -74,16 +85,23 @@ impl Pheromone {
             },
         };
 
-        self.rect.draw_rectangle(color);
+        self.rect.draw_rectangle(Color {
+            r: color.r * brightness,
+            g: color.g * brightness,
+            b: color.b * brightness,
+            ..color
+        });
     }
 
-    pub fn tick(&mut self, dt: f32) {
+    /// Decays the pheromone by `decay_rate` per second
+    pub fn tick(&mut self, dt: f32, decay_rate: f32) {
         if self.locked_intensity || self.decayed {
             // locked pheromones (like those on food cells) don't degrade over time
             return;
         }
 
-        self.intensity *= 1.0 - (dt * PHEROMONE_DECAY_RATE);
+        self.age += dt;
+        self.intensity *= 1.0 - (dt * decay_rate);
         if self.intensity < PHEROMONE_DETECTION_MINIMUM {
             self.decayed = true
         }
@@ -96,6 +114,7 @@ impl Pheromone {
 
         // cap intensity at intensity max
         self.intensity = (self.intensity + additional_intensity).min(PHEROMONE_INTENSITY_MAX);
+        self.age = 0.;
     }
 
     pub fn decayed(&self) -> bool {
@@ -117,17 +136,45 @@ impl Pheromone {
     pub fn locked_intensity(&self) -> bool {
         self.locked_intensity
     }
+
+    /// How attractive the pheromone is to ants, trading off intensity against age
+    fn desirability(&self, freshness_weight: f32) -> f32 {
+        self.intensity / (1. + freshness_weight * self.age)
+    }
 }
 
+#[derive(Clone)]
 pub struct Pheromones {
     pub entries: HashMap<GridLocation, Pheromone>,
+    freshness_weight: f32,
+    decay_rate: f32,
 }
 
 impl Pheromones {
     pub fn new() -> Self {
         Self {
             entries: HashMap::new(),
+            freshness_weight: PHEROMONE_FRESHNESS_WEIGHT,
+            decay_rate: PHEROMONE_DECAY_RATE,
+        }
+    }
+
+    /// Returns the mean intensity of the pheromones deposited by ants, ignoring locked ones (eg on food
+    /// or home cells). Returns 0 if there are none.
+    pub fn average_intensity(&self) -> f32 {
+        let (total, count) = self
+            .entries
+            .values()
+            .filter(|ph| !ph.locked_intensity())
+            .fold((0., 0), |(total, count), ph| {
+                (total + ph.intensity(), count + 1)
+            });
+
+        if count == 0 {
+            return 0.;
         }
+
+        total / count as f32
     }
 
     /// Returns the pheromone that the ant should turn towards, if any
@@ -140,10 +187,15 @@ impl Pheromones {
     ) -> Option<Pheromone> {
         self.get_nearby_pheromones(grid, ant_rect, rotation, search_radius)
             .iter()
-            .max_by(|p1, p2| p1.intensity().total_cmp(&p2.intensity()))
+            .max_by(|p1, p2| self.compare_desirability(p1, p2))
             .map(|ph| **ph)
     }
 
+    fn compare_desirability(&self, p1: &Pheromone, p2: &Pheromone) -> Ordering {
+        p1.desirability(self.freshness_weight)
+            .total_cmp(&p2.desirability(self.freshness_weight))
+    }
+
     fn get_nearby_pheromones(
         &self,
         grid: &WorldGrid,
@@ -160,8 +212,8 @@ impl Pheromones {
                 .iter()
                 // get all the pheromones occupying the cells in that direction
                 .filter_map(|loc| self.entries.get(loc))
-                // keep only the most intense pheromone in that direction
-                .max_by(|p1, p2| p1.intensity.total_cmp(&p2.intensity))
+                // keep only the most desirable pheromone in that direction
+                .max_by(|p1, p2| self.compare_desirability(p1, p2))
             {
                 results.push(most_intense_pheromone);
             }
@@ -170,12 +222,22 @@ impl Pheromones {
         results
     }
 
-    pub fn tick(&mut self, dt: f32) {
+    pub fn decay_rate(&self) -> f32 {
+        self.decay_rate
+    }
+
+    pub fn set_decay_rate(&mut self, decay_rate: f32) {
+        self.decay_rate = decay_rate;
+    }
+
+    /// Decays all pheromones, `decay_multiplier` speeding up or slowing down decay in some regions
+    pub fn tick(&mut self, dt: f32, decay_multiplier: impl Fn(GridLocation) -> f32 + Sync) {
+        let decay_rate = self.decay_rate;
         let expired_pheromone_locs: Vec<GridLocation> = self
             .entries
             .par_iter_mut()
             .fold(HashSet::new, |mut expired_pheromones, (loc, pheromone)| {
-                pheromone.tick(dt);
+                pheromone.tick(dt, decay_rate * decay_multiplier(*loc));
                 if pheromone.decayed() {
                     expired_pheromones.insert(*loc);
                 }
@@ -188,3 +250,59 @@ impl Pheromones {
         }
     }
 }
+
+#[test]
+fn test_average_intensity() {
+    let mut pheromones = Pheromones::new();
+    assert_eq!(pheromones.average_intensity(), 0.);
+
+    let rect = Rect::new(0., 0., 1., 1.);
+    for (i, intensity) in [1., 2., 6.].into_iter().enumerate() {
+        pheromones.entries.insert(
+            GridLocation::new(0, i),
+            Pheromone::new(intensity, PheromoneType::Food, rect, false),
+        );
+    }
+    // locked pheromones don't count towards the average
+    pheromones.entries.insert(
+        GridLocation::new(1, 0),
+        Pheromone::new(SPECIAL_PHEROMONE_INTENSITY, PheromoneType::Food, rect, true),
+    );
+
+    assert_eq!(pheromones.average_intensity(), 3.);
+}
+
+#[test]
+fn test_freshness_weighting() {
+    let grid = WorldGrid::new(&[], 800., 600.);
+    let ant_rect = grid.get_rect_from_loc(GridLocation::new(50, 50));
+
+    // two equally strong pheromones, one straight ahead and one diagonally ahead of an ant facing right
+    let ahead = GridLocation::new(50, 55);
+    let diagonal = GridLocation::new(45, 55);
+
+    let mut pheromones = Pheromones::new();
+    for (loc, age) in [(ahead, 10.), (diagonal, 1.)] {
+        let mut ph = grid.create_pheromone_for_loc(loc, PheromoneType::Food, 1., false);
+        ph.age = age;
+        pheromones.entries.insert(loc, ph);
+    }
+
+    let target = |pheromones: &Pheromones| {
+        let ph = pheromones
+            .get_pheromone_to_target(&grid, &ant_rect, 0., 100.)
+            .expect("both pheromones are in view");
+        grid.get_grid_location_for_rect(ph.rect()).unwrap()
+    };
+
+    pheromones.freshness_weight = 10.;
+    assert_eq!(target(&pheromones), diagonal);
+
+    // the fresher pheromone also wins when it's somewhat weaker
+    pheromones.entries.get_mut(&diagonal).unwrap().intensity = 0.5;
+    assert_eq!(target(&pheromones), diagonal);
+
+    // but not without freshness weighting
+    pheromones.freshness_weight = 0.;
+    assert_eq!(target(&pheromones), ahead);
+}
@@ -0,0 +1,1223 @@
+use std::collections::HashMap;
+use std::f32::consts::PI;
+use std::fmt;
+use std::fs::{self, File};
+use std::io::{self, BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{Receiver, Sender, TryRecvError, channel};
+use std::sync::{Arc, Mutex};
+use std::thread::JoinHandle;
+use std::time::{Duration, Instant};
+
+use image::ImageResult;
+use macroquad::math::Vec2;
+use macroquad::rand::gen_range;
+use rayon::prelude::*;
+use rayon::{ThreadPool, ThreadPoolBuilder};
+
+use crate::ANT_COUNT;
+use crate::ant::{Ant, AntSnapshot, AntState, AntTickResult};
+use crate::grid::{CellType, GRID_HEIGHT, GRID_WIDTH, GridLocation, WorldGrid};
+use crate::pheromone::{Pheromone, PheromoneType};
+
+const HOME_CELLS: usize = 10; // width and height of the square home base, in cells
+const HOME_START_ROW: usize = GRID_HEIGHT / 2 - HOME_CELLS / 2;
+const HOME_START_COL: usize = GRID_WIDTH / 2 - HOME_CELLS / 2;
+const ANT_SPAWN_RAMP_DURATION: f32 = 0.; // simulated seconds over which ants are gradually spawned, 0 spawns them all at once
+const SPAWN_TOWARDS_FOOD_TRAILS: bool = false; // send new ants off towards the strongest food pheromones instead of any direction
+const SPAWN_HEADING_SPREAD: f32 = PI / 4.; // how far new ants' headings may stray from the food trails
+const MAX_ANTS_PER_CELL: usize = usize::MAX; // ants trying to walk into a full cell bounce off instead
+const CROWDED_DEPOSIT_PENALTY: f32 = 0.; // how much each other ant in the cell weakens deposits, 0 disables it
+const MAX_NEW_PHEROMONES_PER_STEP: usize = usize::MAX; // deposits past this that would add new map entries are dropped
+const TRAIL_MIN_INTENSITY: f32 = 1.; // weaker pheromones aren't considered part of a trail
+const TRAIL_LOOP_MIN_CELLS: usize = 8;
+const SIMULATION_THREADS: Option<usize> = None; // pin ant and pheromone ticks to a dedicated pool, None uses all cores
+const TARGET_PHEROMONE_COVERAGE: Option<f32> = None; // adjust the decay rate to keep this share of the map covered
+const DECAY_TUNING_GAIN: f32 = 2.; // decay rate change per second for each unit of coverage off target
+const MIN_TUNED_DECAY_RATE: f32 = 0.05;
+const MAX_TUNED_DECAY_RATE: f32 = 10.;
+const BACKGROUND_STEP_INTERVAL: Duration = Duration::from_millis(8); // how often the background thread steps
+
+/// The simulated world, independent of rendering
+pub struct Simulation {
+    ants: Vec<Ant>,
+    grid: WorldGrid,
+    max_ants_per_cell: usize,
+    crowded_deposit_penalty: f32,
+    max_new_pheromones_per_step: usize,
+    ticks: u64,        // how many times the simulation has been stepped
+    elapsed_time: f32, // simulated seconds
+    ant_spawn_point: Vec2,
+    spawn_ramp_duration: f32,
+    spawn_towards_food_trails: bool,
+    thread_pool: Option<Arc<ThreadPool>>, // uses rayon's global pool when not set
+    target_coverage: Option<f32>,
+    food_cleared_at: Option<(u64, f32)>, // tick and simulated time at which the last food was delivered
+    heat_frames: Option<HeatFrameExport>,
+}
+
+/// Where and how often pheromone heat frames are saved
+struct HeatFrameExport {
+    directory: PathBuf,
+    interval: u64, // in ticks
+    frames_written: usize,
+}
+
+impl Simulation {
+    pub fn new(screen_width: f32, screen_height: f32) -> Self {
+        Self::with_spawn_ramp(screen_width, screen_height, ANT_SPAWN_RAMP_DURATION)
+    }
+
+    fn with_spawn_ramp(screen_width: f32, screen_height: f32, spawn_ramp_duration: f32) -> Self {
+        let home_locs = nest_locations();
+        let grid = WorldGrid::new(home_locs.as_slice(), screen_width, screen_height);
+
+        let grid_center_loc = GridLocation::new(
+            HOME_START_ROW + HOME_CELLS / 2,
+            HOME_START_COL + HOME_CELLS / 2,
+        );
+        let ant_spawn_point = grid.get_rect_from_loc(grid_center_loc).center();
+
+        let mut simulation = Self {
+            ants: Vec::with_capacity(ANT_COUNT),
+            grid,
+            max_ants_per_cell: MAX_ANTS_PER_CELL,
+            crowded_deposit_penalty: CROWDED_DEPOSIT_PENALTY,
+            max_new_pheromones_per_step: MAX_NEW_PHEROMONES_PER_STEP,
+            ticks: 0,
+            elapsed_time: 0.,
+            ant_spawn_point,
+            spawn_ramp_duration,
+            spawn_towards_food_trails: SPAWN_TOWARDS_FOOD_TRAILS,
+            thread_pool: SIMULATION_THREADS.map(build_thread_pool),
+            target_coverage: TARGET_PHEROMONE_COVERAGE,
+            food_cleared_at: None,
+            heat_frames: None,
+        };
+        simulation.spawn_scheduled_ants();
+        simulation
+    }
+
+    /// Tops up the colony to the number of ants it should have at the current simulated time
+    fn spawn_scheduled_ants(&mut self) {
+        let target_ant_count = if self.spawn_ramp_duration > 0. {
+            let ramp_progress = (self.elapsed_time / self.spawn_ramp_duration).min(1.);
+            (ANT_COUNT as f32 * ramp_progress) as usize
+        } else {
+            ANT_COUNT
+        };
+
+        if self.ants.len() >= target_ant_count {
+            return;
+        }
+
+        let food_trail_heading = if self.spawn_towards_food_trails {
+            self.food_trail_heading()
+        } else {
+            None
+        };
+
+        while self.ants.len() < target_ant_count {
+            let ant = Ant::new(self.ant_spawn_point.x, self.ant_spawn_point.y, &self.grid);
+            self.ants.push(match food_trail_heading {
+                Some(heading) => ant.with_rotation(
+                    heading + gen_range(-SPAWN_HEADING_SPREAD, SPAWN_HEADING_SPREAD),
+                ),
+                None => ant,
+            });
+        }
+    }
+
+    /// Returns the direction from the nest in which food pheromones laid by ants are strongest overall,
+    /// favoring nearby trails. Returns None if there are none.
+    fn food_trail_heading(&self) -> Option<f32> {
+        let pull: Vec2 = self
+            .grid
+            .pheromones(PheromoneType::Food)
+            .entries
+            .values()
+            // locked pheromones sit on the food itself, trails show where ants are actually recruited
+            .filter(|ph| !ph.locked_intensity())
+            .map(|ph| {
+                let offset = ph.rect().center() - self.ant_spawn_point;
+                offset.normalize_or_zero() * ph.intensity() / offset.length().max(1.)
+            })
+            .sum();
+
+        (pull != Vec2::ZERO).then(|| pull.y.atan2(pull.x))
+    }
+
+    /// Runs `n` steps of fixed length, for deterministic and fast tests
+    #[cfg(test)]
+    pub fn step_n(&mut self, dt: f32, n: u64) {
+        for _ in 0..n {
+            self.step(dt);
+        }
+    }
+
+    pub fn step(&mut self, dt: f32) {
+        match self.thread_pool.clone() {
+            Some(pool) => pool.install(|| self.step_in_current_pool(dt)),
+            None => self.step_in_current_pool(dt),
+        }
+    }
+
+    fn step_in_current_pool(&mut self, dt: f32) {
+        self.ticks += 1;
+        self.elapsed_time += dt;
+        self.grid.tick(dt);
+        let grid = &self.grid;
+        let capped = self.max_ants_per_cell != usize::MAX;
+        let ant_state_updates: Vec<(Option<Ant>, AntTickResult)> = self
+            .ants
+            .par_iter_mut()
+            .map(|ant| {
+                // keep the pre-tick state around in case the move gets rejected below
+                let previous = capped.then(|| ant.clone());
+                let update = ant.tick(grid, dt);
+                (previous, update)
+            })
+            .collect();
+
+        let rejected_moves = if capped {
+            let moves: Vec<(GridLocation, GridLocation)> = ant_state_updates
+                .iter()
+                .map(|(previous, (loc, _, _))| {
+                    let previous = previous
+                        .as_ref()
+                        .expect("capped steps keep the previous state");
+                    (self.ant_location(previous), *loc)
+                })
+                .collect();
+            reject_moves_into_full_cells(&moves, self.max_ants_per_cell)
+        } else {
+            vec![false; ant_state_updates.len()]
+        };
+
+        // where every ant ends up this step, only needed to weaken crowded deposits
+        let density = (self.crowded_deposit_penalty != 0.).then(|| {
+            let mut density: HashMap<GridLocation, usize> = HashMap::new();
+            for ((previous, (loc, _, _)), rejected) in ant_state_updates.iter().zip(&rejected_moves)
+            {
+                let final_loc = match previous {
+                    Some(previous) if *rejected => self.ant_location(previous),
+                    _ => *loc,
+                };
+                *density.entry(final_loc).or_default() += 1;
+            }
+            density
+        });
+
+        let mut new_pheromones_budget = self.max_new_pheromones_per_step;
+
+        for ((ant, (previous, (loc, ph, action))), rejected) in self
+            .ants
+            .iter_mut()
+            .zip(ant_state_updates)
+            .zip(rejected_moves)
+        {
+            if let Some(previous) = previous.filter(|_| rejected) {
+                // cell is full, the ant stays where it was
+                ant.undo_tick(previous);
+                continue;
+            }
+
+            // deposit pheromone on the grid if it was spawned by the ant
+            if let Some(pheromone) = ph {
+                let is_new = !self
+                    .grid
+                    .pheromones(*pheromone.pheromone_type())
+                    .entries
+                    .contains_key(&loc);
+                // inserting into the pheromone maps is the expensive part, reinforcing existing
+                // pheromones is always allowed
+                if !is_new || new_pheromones_budget > 0 {
+                    if is_new {
+                        new_pheromones_budget -= 1;
+                    }
+                    self.grid.deposit_pheromone(match &density {
+                        Some(density) => weaken_crowded_deposit(
+                            pheromone,
+                            density[&loc],
+                            self.crowded_deposit_penalty,
+                        ),
+                        None => pheromone,
+                    })
+                }
+            }
+            self.grid.visit_cell(loc, action);
+        }
+
+        self.tune_decay_rate(dt);
+        self.record_food_cleared();
+        self.write_heat_frame();
+        self.spawn_scheduled_ants();
+    }
+
+    /// Proportional controller nudging the pheromone decay rate up when the map is covered in trails past
+    /// the target, and down when trails are too sparse. Locked pheromones on the nest and food don't count
+    /// since no decay rate can clear them.
+    fn tune_decay_rate(&mut self, dt: f32) {
+        let Some(target_coverage) = self.target_coverage else {
+            return;
+        };
+
+        let coverage = (self.grid.trail_coverage(PheromoneType::Food)
+            + self.grid.trail_coverage(PheromoneType::Home))
+            / 2.;
+        let decay_rate =
+            self.grid.decay_rate() + DECAY_TUNING_GAIN * (coverage - target_coverage) * dt;
+        self.grid
+            .set_decay_rate(decay_rate.clamp(MIN_TUNED_DECAY_RATE, MAX_TUNED_DECAY_RATE));
+    }
+
+    /// Saves the pheromone field as a numbered grayscale image every `interval` ticks from now on. The
+    /// frames can be assembled into an animation of how trails form, eg with
+    /// `ffmpeg -i <directory>/frame_%05d.png trails.mp4`. Numbering carries on after the frames already in
+    /// the directory, eg from before a reset, so they don't get overwritten.
+    pub fn export_heat_frames(
+        &mut self,
+        directory: impl AsRef<Path>,
+        interval: u64,
+    ) -> io::Result<()> {
+        fs::create_dir_all(&directory)?;
+        let mut frames_written = 0;
+        for entry in fs::read_dir(&directory)? {
+            let name = entry?.file_name();
+            let frame = name
+                .to_str()
+                .and_then(|name| name.strip_prefix("frame_")?.strip_suffix(".png"))
+                .and_then(|number| number.parse::<usize>().ok());
+            if let Some(frame) = frame {
+                frames_written = frames_written.max(frame + 1);
+            }
+        }
+
+        self.heat_frames = Some(HeatFrameExport {
+            directory: directory.as_ref().to_path_buf(),
+            interval: interval.max(1),
+            frames_written,
+        });
+        Ok(())
+    }
+
+    fn write_heat_frame(&mut self) {
+        let Some(export) = &mut self.heat_frames else {
+            return;
+        };
+        if !self.ticks.is_multiple_of(export.interval) {
+            return;
+        }
+
+        let path = export
+            .directory
+            .join(format!("frame_{:05}.png", export.frames_written));
+        match self.grid.pheromone_heat_image().save(&path) {
+            Ok(()) => export.frames_written += 1,
+            Err(err) => {
+                // don't keep failing every few ticks
+                eprintln!(
+                    "Failed to save heat frame to {}: {}, stopping the export",
+                    path.display(),
+                    err
+                );
+                self.heat_frames = None;
+            }
+        }
+    }
+
+    /// Notes the moment the map runs out of food and the last of it has been delivered to the nest
+    fn record_food_cleared(&mut self) {
+        if self.food_cleared_at.is_some()
+            || self.grid.food_collected() == 0
+            || self.grid.food_remaining() > 0
+            || self
+                .ants
+                .iter()
+                .any(|ant| ant.state() == AntState::CarryingFood)
+        {
+            return;
+        }
+
+        self.food_cleared_at = Some((self.ticks, self.elapsed_time));
+    }
+
+    /// Returns true once all food on the map has been collected
+    pub fn food_cleared(&self) -> bool {
+        self.food_cleared_at.is_some()
+    }
+
+    /// Steps the simulation with a fixed `dt` until the simulated time reaches `max_simulated_time`, or
+    /// optionally until all food has been collected
+    pub fn run_for(
+        &mut self,
+        dt: f32,
+        max_simulated_time: f32,
+        until_food_cleared: bool,
+    ) -> RunSummary {
+        while self.elapsed_time < max_simulated_time && !(until_food_cleared && self.food_cleared())
+        {
+            self.step(dt);
+        }
+        self.summary()
+    }
+
+    /// Returns the fraction of the map covered by pheromones of the given type. Values close to 1 mean the
+    /// field is saturated and no longer guides ants anywhere in particular.
+    pub fn coverage(&self, pheromone_type: PheromoneType) -> f32 {
+        self.grid.coverage(pheromone_type)
+    }
+
+    pub fn summary(&self) -> RunSummary {
+        RunSummary {
+            ticks: self.ticks,
+            simulated_time: self.elapsed_time,
+            food_collected: self.grid.food_collected(),
+            food_remaining: self.grid.food_remaining(),
+            food_cleared_at: self.food_cleared_at,
+            food_pheromone_coverage: self.coverage(PheromoneType::Food),
+            home_pheromone_coverage: self.coverage(PheromoneType::Home),
+            food_pheromone_average_intensity: self
+                .grid
+                .pheromones(PheromoneType::Food)
+                .average_intensity(),
+            home_pheromone_average_intensity: self
+                .grid
+                .pheromones(PheromoneType::Home)
+                .average_intensity(),
+        }
+    }
+
+    #[cfg(test)]
+    pub fn ticks(&self) -> u64 {
+        self.ticks
+    }
+
+    fn ant_location(&self, ant: &Ant) -> GridLocation {
+        self.grid
+            .get_grid_location_for_rect(ant.rect())
+            .expect("Ants should never walk off the world grid.")
+    }
+
+    pub fn spawn_cells(&mut self, x: f32, y: f32, cell_type: CellType) {
+        if let CellType::Food(_) = cell_type {
+            // there's food to collect again
+            self.food_cleared_at = None;
+        }
+        self.grid.spawn_cells(x, y, cell_type)
+    }
+
+    pub fn grid(&self) -> &WorldGrid {
+        &self.grid
+    }
+
+    pub fn ant_snapshots(&self) -> Vec<AntSnapshot> {
+        self.ants.iter().map(|ant| ant.snapshot()).collect()
+    }
+
+    /// Returns the cells of closed food trails, which can trap ants circling them endlessly
+    pub fn detect_trail_loops(&self) -> Vec<Vec<GridLocation>> {
+        self.grid.find_trail_loops(
+            PheromoneType::Food,
+            TRAIL_MIN_INTENSITY,
+            TRAIL_LOOP_MIN_CELLS,
+        )
+    }
+
+    pub fn report_trail_loops(&self) {
+        let trail_loops = self.detect_trail_loops();
+        println!("Detected {} food trail loop(s)", trail_loops.len());
+        for trail_loop in trail_loops {
+            println!(
+                "  loop of {} cells through {:?}",
+                trail_loop.len(),
+                trail_loop[0]
+            );
+        }
+    }
+
+    /// Returns the length of the food trail leading from `source` to the nest, if ants have established
+    /// one. Compare it with the straight line distance to see how close to optimal the route is.
+    pub fn established_trail_length(&self, source: GridLocation) -> Option<f32> {
+        self.grid
+            .trail_length_to_home(PheromoneType::Food, source, TRAIL_MIN_INTENSITY)
+    }
+
+    pub fn report_trail_length(&self, x: f32, y: f32) {
+        let Some(source) = self.grid.get_grid_location(x, y) else {
+            return;
+        };
+
+        // the trail ends at the edge of the nest, so compare it with the distance to the edge too
+        let Some(straight_line) = self
+            .grid
+            .distance_to_nest(self.grid.get_rect_from_loc(source).center())
+        else {
+            return;
+        };
+        match self.established_trail_length(source) {
+            Some(_) if straight_line <= f32::EPSILON => println!("This is the nest"),
+            Some(length) => println!(
+                "Trail to the nest is {:.1} long, {:.2}x the straight line distance of {:.1}",
+                length,
+                length / straight_line,
+                straight_line
+            ),
+            None => println!("No established trail from here to the nest"),
+        }
+    }
+
+    /// Writes the current food and home pheromone field to a CSV file, which can be loaded back with
+    /// `load_pheromone_field`
+    pub fn dump_pheromone_field(&self, path: impl AsRef<Path>) -> io::Result<()> {
+        let mut out = BufWriter::new(File::create(path)?);
+        self.grid.write_pheromone_field(&mut out)?;
+        out.flush()
+    }
+
+    pub fn report_pheromone_field_dump(&self, path: &Path) {
+        match self.dump_pheromone_field(path) {
+            Ok(()) => println!("Saved pheromone field to {}", path.display()),
+            Err(err) => eprintln!(
+                "Failed to save pheromone field to {}: {}",
+                path.display(),
+                err
+            ),
+        }
+    }
+
+    /// Seeds the pheromone maps from a field file, eg to see how ants respond to a pre-laid trail
+    pub fn load_pheromone_field(&mut self, path: impl AsRef<Path>) -> io::Result<()> {
+        let input = fs::read_to_string(path)?;
+        self.grid.read_pheromone_field(&input)
+    }
+
+    /// Saves a one-image overview of what the colony did so far, see `WorldGrid::render_summary_image`
+    pub fn render_summary_image(&self, path: impl AsRef<Path>) -> ImageResult<()> {
+        self.grid.render_summary_image(path)
+    }
+
+    pub fn snapshot(&self) -> SimulationSnapshot {
+        SimulationSnapshot {
+            grid: self.grid.clone(),
+            ants: self.ant_snapshots(),
+            summary: self.summary(),
+        }
+    }
+}
+
+fn build_thread_pool(threads: usize) -> Arc<ThreadPool> {
+    let pool = ThreadPoolBuilder::new()
+        .num_threads(threads)
+        .thread_name(|i| format!("simulation-{}", i))
+        .build()
+        .expect("failed to build the simulation thread pool");
+    Arc::new(pool)
+}
+
+/// Cells making up the nest, a square in the middle of the grid
+pub fn nest_locations() -> Vec<GridLocation> {
+    (HOME_START_ROW..HOME_START_ROW + HOME_CELLS)
+        .flat_map(|r| {
+            (HOME_START_COL..HOME_START_COL + HOME_CELLS).map(move |c| GridLocation::new(r, c))
+        })
+        .collect()
+}
+
+/// Busy cells need less recruiting, so each additional ant in the cell weakens the deposit
+fn weaken_crowded_deposit(pheromone: Pheromone, ants_in_cell: usize, penalty: f32) -> Pheromone {
+    let other_ants = ants_in_cell.saturating_sub(1) as f32;
+    pheromone.with_intensity(pheromone.intensity() / (1. + penalty * other_ants))
+}
+
+/// Given each ant's (previous, new) location, returns which moves have to be rejected so that no cell
+/// receives ants past the cap. Ants that stay put are never pushed out, and rejected ants return to
+/// their previous cell, which can in turn push out ants that moved there.
+fn reject_moves_into_full_cells(moves: &[(GridLocation, GridLocation)], cap: usize) -> Vec<bool> {
+    let mut rejected = vec![false; moves.len()];
+
+    loop {
+        let mut density: HashMap<GridLocation, usize> = HashMap::new();
+        let mut movers: HashMap<GridLocation, Vec<usize>> = HashMap::new();
+        for (i, (previous_loc, loc)) in moves.iter().enumerate() {
+            if rejected[i] {
+                *density.entry(*previous_loc).or_default() += 1;
+            } else {
+                *density.entry(*loc).or_default() += 1;
+                if loc != previous_loc {
+                    movers.entry(*loc).or_default().push(i);
+                }
+            }
+        }
+
+        let mut changed = false;
+        for (loc, count) in density {
+            if count <= cap {
+                continue;
+            }
+            // turn back the last ants to arrive until the cell is no longer over capacity
+            for &i in movers
+                .get(&loc)
+                .into_iter()
+                .flatten()
+                .rev()
+                .take(count - cap)
+            {
+                rejected[i] = true;
+                changed = true;
+            }
+        }
+
+        if !changed {
+            return rejected;
+        }
+    }
+}
+
+/// A copy of the simulation state that can be handed over to the renderer
+pub struct SimulationSnapshot {
+    pub grid: WorldGrid,
+    pub ants: Vec<AntSnapshot>,
+    pub summary: RunSummary,
+}
+
+/// Headline numbers of a simulation run, printed when a run ends
+#[derive(Copy, Clone, Debug)]
+pub struct RunSummary {
+    pub ticks: u64,
+    pub simulated_time: f32,
+    pub food_collected: u32,
+    pub food_remaining: u32,
+    pub food_cleared_at: Option<(u64, f32)>, // tick and simulated time
+    pub food_pheromone_coverage: f32,
+    pub home_pheromone_coverage: f32,
+    pub food_pheromone_average_intensity: f32, // of the pheromones laid by ants
+    pub home_pheromone_average_intensity: f32,
+}
+
+impl fmt::Display for RunSummary {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "Run summary:")?;
+        writeln!(f, "  ticks: {}", self.ticks)?;
+        writeln!(f, "  simulated time: {:.2}s", self.simulated_time)?;
+        writeln!(f, "  food collected: {}", self.food_collected)?;
+        writeln!(f, "  food remaining: {}", self.food_remaining)?;
+        writeln!(
+            f,
+            "  food pheromone coverage: {:.1}%",
+            self.food_pheromone_coverage * 100.
+        )?;
+        writeln!(
+            f,
+            "  home pheromone coverage: {:.1}%",
+            self.home_pheromone_coverage * 100.
+        )?;
+        writeln!(
+            f,
+            "  food pheromone average intensity: {:.2}",
+            self.food_pheromone_average_intensity
+        )?;
+        write!(
+            f,
+            "  home pheromone average intensity: {:.2}",
+            self.home_pheromone_average_intensity
+        )?;
+        if let Some((ticks, simulated_time)) = self.food_cleared_at {
+            write!(
+                f,
+                "\n  all food collected after: {:.2}s ({} ticks)",
+                simulated_time, ticks
+            )?;
+        }
+        Ok(())
+    }
+}
+
+pub enum SimulationCommand {
+    SpawnCells(f32, f32, CellType),
+    SetPaused(bool),
+    Reset,
+    ReportTrailLoops,
+    ReportTrailLength(f32, f32),
+    DumpPheromoneField(PathBuf),
+}
+
+/// Handle to a simulation stepping on its own thread. Dropping it stops the thread.
+pub struct BackgroundSimulation {
+    commands: Sender<SimulationCommand>,
+    snapshot: SnapshotSlot,
+    handle: JoinHandle<()>,
+}
+
+/// Holds the snapshot the renderer hasn't picked up yet. The background thread only builds a new one
+/// once the slot has been emptied, since cloning the grid is expensive.
+type SnapshotSlot = Arc<Mutex<Option<SimulationSnapshot>>>;
+
+impl BackgroundSimulation {
+    /// Starts stepping a simulation built by `new_simulation`, which is also used to rebuild it on reset
+    pub fn spawn(new_simulation: impl Fn() -> Simulation + Send + 'static) -> Self {
+        let (commands, command_receiver) = channel();
+        let snapshot = SnapshotSlot::default();
+
+        let slot = Arc::clone(&snapshot);
+        let handle =
+            std::thread::spawn(move || run_in_background(new_simulation, command_receiver, slot));
+
+        Self {
+            commands,
+            snapshot,
+            handle,
+        }
+    }
+
+    pub fn send(&self, command: SimulationCommand) {
+        // the thread only exits once this handle is dropped, so sending can't fail
+        let _ = self.commands.send(command);
+    }
+
+    /// Blocks until the simulation produces its next snapshot
+    pub fn wait_for_snapshot(&self) -> SimulationSnapshot {
+        loop {
+            if let Some(snapshot) = self.latest_snapshot() {
+                return snapshot;
+            }
+            assert!(
+                !self.handle.is_finished(),
+                "background simulation should keep running while the handle is alive"
+            );
+            std::thread::sleep(BACKGROUND_STEP_INTERVAL);
+        }
+    }
+
+    /// Returns the most recent snapshot produced since the last call, if any
+    pub fn latest_snapshot(&self) -> Option<SimulationSnapshot> {
+        self.snapshot.lock().unwrap().take()
+    }
+}
+
+fn run_in_background(
+    new_simulation: impl Fn() -> Simulation,
+    commands: Receiver<SimulationCommand>,
+    snapshot: SnapshotSlot,
+) {
+    let mut simulation = new_simulation();
+    let mut paused = false;
+    let mut last_step = Instant::now();
+
+    loop {
+        loop {
+            match commands.try_recv() {
+                Ok(SimulationCommand::SpawnCells(x, y, cell_type)) => {
+                    simulation.spawn_cells(x, y, cell_type)
+                }
+                Ok(SimulationCommand::SetPaused(p)) => paused = p,
+                Ok(SimulationCommand::Reset) => {
+                    simulation = new_simulation();
+                    paused = false;
+                }
+                Ok(SimulationCommand::ReportTrailLoops) => simulation.report_trail_loops(),
+                Ok(SimulationCommand::ReportTrailLength(x, y)) => {
+                    simulation.report_trail_length(x, y)
+                }
+                Ok(SimulationCommand::DumpPheromoneField(path)) => {
+                    simulation.report_pheromone_field_dump(&path)
+                }
+                Err(TryRecvError::Empty) => break,
+                Err(TryRecvError::Disconnected) => return, // renderer is gone
+            }
+        }
+
+        let dt = last_step.elapsed().as_secs_f32();
+        last_step = Instant::now();
+        if !paused {
+            simulation.step(dt);
+        }
+
+        // the renderer hasn't picked up the previous snapshot yet, don't bother building another one.
+        // Only this thread fills the slot, so it's still empty once the new snapshot is ready.
+        if snapshot.lock().unwrap().is_none() {
+            let next = simulation.snapshot();
+            *snapshot.lock().unwrap() = Some(next);
+        }
+
+        std::thread::sleep(BACKGROUND_STEP_INTERVAL);
+    }
+}
+
+#[test]
+fn test_background_simulation_produces_snapshots() {
+    let background = BackgroundSimulation::spawn(|| Simulation::new(800., 600.));
+
+    let snapshot = background.wait_for_snapshot();
+    assert_eq!(snapshot.ants.len(), ANT_COUNT);
+
+    // the simulation keeps stepping on its own thread
+    let next_snapshot = background.wait_for_snapshot();
+    assert!(
+        snapshot
+            .ants
+            .iter()
+            .zip(next_snapshot.ants.iter())
+            .any(|(before, after)| before.rect() != after.rect())
+    );
+}
+
+#[test]
+fn test_ants_per_cell_cap() {
+    let mut simulation = Simulation::new(800., 600.);
+    simulation.max_ants_per_cell = 3;
+
+    let ant_density = |simulation: &Simulation| {
+        let mut density: HashMap<GridLocation, usize> = HashMap::new();
+        for ant in &simulation.ants {
+            *density.entry(simulation.ant_location(ant)).or_default() += 1;
+        }
+        density
+    };
+
+    for _ in 0..20 {
+        let density_before = ant_density(&simulation);
+        simulation.step(0.05);
+
+        // no cell gains ants past the cap. Cells that were already over it (eg the spawn point) may only shrink.
+        for (loc, count) in ant_density(&simulation) {
+            let count_before = density_before.get(&loc).copied().unwrap_or(0);
+            assert!(count <= simulation.max_ants_per_cell.max(count_before));
+        }
+    }
+
+    // spread the colony out so that every cell starts below the cap, which it then never exceeds
+    let mut simulation = Simulation::new(800., 600.);
+    simulation.max_ants_per_cell = 3;
+    let cells = (65..85).flat_map(|r| (88..113).map(move |c| GridLocation::new(r, c)));
+    simulation.ants = cells
+        .flat_map(|loc| {
+            let center = simulation.grid.get_rect_from_loc(loc).center();
+            [
+                Ant::new(center.x, center.y, &simulation.grid),
+                Ant::new(center.x, center.y, &simulation.grid),
+            ]
+        })
+        .collect();
+    assert_eq!(simulation.ants.len(), ANT_COUNT);
+    assert!(ant_density(&simulation).values().all(|&count| count < 3));
+
+    for _ in 0..20 {
+        simulation.step(0.05);
+        assert!(ant_density(&simulation).values().all(|&count| count <= 3));
+    }
+}
+
+#[test]
+fn test_step_n() {
+    let mut simulation = Simulation::new(800., 600.);
+    simulation.step_n(1. / 60., 10);
+    assert_eq!(simulation.ticks(), 10);
+}
+
+#[test]
+fn test_gradual_ant_spawning() {
+    let mut simulation = Simulation::with_spawn_ramp(800., 600., 1.);
+    assert_eq!(simulation.ants.len(), 0);
+
+    let mut previous_count = 0;
+    for _ in 0..10 {
+        simulation.step(0.1);
+        assert!(simulation.ants.len() > previous_count);
+        previous_count = simulation.ants.len();
+    }
+    assert_eq!(simulation.ants.len(), ANT_COUNT);
+
+    // no more ants once the target is reached
+    simulation.step(0.1);
+    assert_eq!(simulation.ants.len(), ANT_COUNT);
+}
+
+#[test]
+fn test_crowded_deposits_are_weaker() {
+    let grid = WorldGrid::new(&[], 800., 600.);
+    let pheromone =
+        grid.create_pheromone_for_loc(GridLocation::new(10, 10), PheromoneType::Food, 1., false);
+
+    let lone_deposit = weaken_crowded_deposit(pheromone, 1, 0.5);
+    let crowded_deposit = weaken_crowded_deposit(pheromone, 5, 0.5);
+    assert_eq!(lone_deposit.intensity(), 1.);
+    assert_eq!(crowded_deposit.intensity(), 1. / 3.);
+
+    // no penalty, no difference
+    assert_eq!(weaken_crowded_deposit(pheromone, 5, 0.).intensity(), 1.);
+}
+
+#[test]
+fn test_crowded_cells_get_weaker_deposits() {
+    let mut simulation = Simulation::new(800., 600.);
+    simulation.crowded_deposit_penalty = 0.5;
+
+    // four ants sharing a cell and one on its own, all walking diagonally so they stay in their cells
+    let crowded_cell = GridLocation::new(30, 30);
+    let lone_cell = GridLocation::new(30, 60);
+    let ant_at = |loc| {
+        let center = simulation.grid.get_rect_from_loc(loc).center();
+        let mut ant = Ant::new(center.x, center.y, &simulation.grid).with_rotation(PI / 4.);
+        ant.delay_next_check();
+        ant
+    };
+    simulation.ants = std::iter::repeat_with(|| ant_at(crowded_cell))
+        .take(4)
+        .chain(std::iter::once(ant_at(lone_cell)))
+        .collect();
+
+    // long enough for every ant to lay exactly one pheromone
+    simulation.step(0.015);
+
+    let intensity_at =
+        |loc| simulation.grid.pheromones(PheromoneType::Home).entries[&loc].intensity();
+    let lone_deposit = intensity_at(lone_cell);
+    assert!((intensity_at(crowded_cell) - 4. * lone_deposit / 2.5).abs() < 0.001);
+}
+
+#[test]
+fn test_new_pheromones_per_step_budget() {
+    let mut simulation = Simulation::new(800., 600.);
+    simulation.max_new_pheromones_per_step = 5;
+
+    let pheromone_count = |simulation: &Simulation| {
+        simulation
+            .grid
+            .pheromones(PheromoneType::Food)
+            .entries
+            .len()
+            + simulation
+                .grid
+                .pheromones(PheromoneType::Home)
+                .entries
+                .len()
+    };
+
+    let mut budget_used_up = false;
+    for _ in 0..20 {
+        let count_before = pheromone_count(&simulation);
+        simulation.step(0.05);
+        let new_pheromones = pheromone_count(&simulation).saturating_sub(count_before);
+
+        assert!(new_pheromones <= 5);
+        budget_used_up |= new_pheromones == 5;
+    }
+    assert!(budget_used_up);
+}
+
+#[test]
+fn test_headless_run_stops_at_max_simulated_time() {
+    let mut simulation = Simulation::new(800., 600.);
+    let summary = simulation.run_for(0.25, 2., false);
+
+    // stops on the first step that reaches the limit
+    assert!(summary.simulated_time >= 2.);
+    assert!(summary.simulated_time < 2.25);
+    assert_eq!(summary.ticks, simulation.ticks());
+    assert_eq!(summary.ticks, 8);
+    // ants have been laying home pheromones all along
+    assert!(summary.home_pheromone_average_intensity > 0.);
+    assert!(
+        summary
+            .to_string()
+            .contains("home pheromone average intensity")
+    );
+}
+
+#[test]
+fn test_pheromone_field_round_trip() {
+    let mut simulation = Simulation::new(800., 600.);
+    simulation.spawn_cells(100., 100., CellType::Food(10));
+    simulation.step_n(0.05, 20);
+    // ants have laid a trail of home pheromones on top of the locked ones on the nest
+    assert!(
+        simulation
+            .grid
+            .pheromones(PheromoneType::Home)
+            .average_intensity()
+            > 0.
+    );
+
+    let path = std::env::temp_dir().join(format!("ants_field_{}.csv", std::process::id()));
+    simulation.dump_pheromone_field(&path).unwrap();
+
+    let mut loaded = Simulation::new(800., 600.);
+    loaded.spawn_cells(100., 100., CellType::Food(10));
+    loaded.load_pheromone_field(&path).unwrap();
+    fs::remove_file(&path).unwrap();
+
+    for pheromone_type in [PheromoneType::Food, PheromoneType::Home] {
+        let intensities = |simulation: &Simulation| {
+            let mut intensities: Vec<(GridLocation, f32, bool)> = simulation
+                .grid
+                .pheromones(pheromone_type)
+                .entries
+                .iter()
+                .map(|(loc, ph)| (*loc, ph.intensity(), ph.locked_intensity()))
+                .collect();
+            intensities.sort_by_key(|(loc, _, _)| *loc);
+            intensities
+        };
+        assert_eq!(intensities(&simulation), intensities(&loaded));
+    }
+}
+
+#[test]
+fn test_pheromone_field_dimensions_are_validated() {
+    let mut simulation = Simulation::new(800., 600.);
+    let row = vec!["0"; GRID_WIDTH].join(",");
+
+    let too_few_rows = format!("food\n{}\n", row);
+    assert!(simulation.grid.read_pheromone_field(&too_few_rows).is_err());
+
+    let short_row = format!("home\n{}\n", vec!["1"; GRID_WIDTH - 1].join(","));
+    assert!(simulation.grid.read_pheromone_field(&short_row).is_err());
+}
+
+#[test]
+fn test_render_summary_image() {
+    use crate::grid::SUMMARY_IMAGE_CELL_SIZE;
+
+    let mut simulation = Simulation::new(800., 600.);
+    simulation.step_n(0.05, 10);
+
+    let path = std::env::temp_dir().join(format!("ants_summary_{}.png", std::process::id()));
+    simulation.render_summary_image(&path).unwrap();
+    let image = image::open(&path).unwrap().to_rgb8();
+    fs::remove_file(&path).unwrap();
+
+    assert_eq!(image.width(), GRID_WIDTH as u32 * SUMMARY_IMAGE_CELL_SIZE);
+    assert_eq!(image.height(), GRID_HEIGHT as u32 * SUMMARY_IMAGE_CELL_SIZE);
+
+    // every cell an ant walked into shows up
+    let pixels_per_unit = image.width() as f32 / 800.;
+    for ant in &simulation.ants {
+        let center = ant.rect().center() * pixels_per_unit;
+        let pixel = image.get_pixel(center.x as u32, center.y as u32);
+        assert!(pixel.0.iter().any(|channel| *channel > 0));
+    }
+}
+
+#[test]
+fn test_single_thread_pool_matches_default() {
+    let mut default = Simulation::new(800., 600.);
+    let mut single_threaded = Simulation::new(800., 600.);
+    single_threaded.thread_pool = Some(build_thread_pool(1));
+    assert_eq!(
+        single_threaded
+            .thread_pool
+            .as_ref()
+            .unwrap()
+            .current_num_threads(),
+        1
+    );
+
+    // same ants, walking straight so that random turns don't make the runs diverge
+    single_threaded.ants = default.ants.clone();
+    for simulation in [&mut default, &mut single_threaded] {
+        simulation.ants.iter_mut().for_each(Ant::delay_next_check);
+        for c in 0..GRID_WIDTH {
+            let ph = simulation.grid.create_pheromone_for_loc(
+                GridLocation::new(10, c),
+                PheromoneType::Food,
+                c as f32,
+                false,
+            );
+            simulation.grid.deposit_pheromone(ph);
+        }
+        simulation.step_n(0.002, 40);
+    }
+
+    let ant_rects = |simulation: &Simulation| {
+        simulation
+            .ants
+            .iter()
+            .map(|ant| *ant.rect())
+            .collect::<Vec<_>>()
+    };
+    assert_eq!(ant_rects(&default), ant_rects(&single_threaded));
+
+    for pheromone_type in [PheromoneType::Food, PheromoneType::Home] {
+        let intensities = |simulation: &Simulation| {
+            let mut intensities: Vec<(GridLocation, f32)> = simulation
+                .grid
+                .pheromones(pheromone_type)
+                .entries
+                .iter()
+                .map(|(loc, ph)| (*loc, ph.intensity()))
+                .collect();
+            intensities.sort_by_key(|(loc, _)| *loc);
+            intensities
+        };
+        assert_eq!(intensities(&default), intensities(&single_threaded));
+    }
+}
+
+#[test]
+fn test_food_cleared_is_recorded_when_last_food_is_delivered() {
+    let mut simulation = Simulation::new(800., 600.);
+    // a single unit of food in each cell, right on top of the nest center where ants spawn
+    simulation.spawn_cells(
+        simulation.ant_spawn_point.x,
+        simulation.ant_spawn_point.y,
+        CellType::Food(1),
+    );
+
+    simulation.step(0.05);
+    assert!(simulation.grid.food_remaining() < 25);
+    // some food was picked up, but it hasn't all been delivered yet
+    assert!(!simulation.food_cleared());
+
+    let summary = simulation.run_for(0.05, 30., true);
+    let (ticks, simulated_time) = summary
+        .food_cleared_at
+        .expect("all food should be delivered well within the time limit");
+    assert!(ticks > 1);
+    assert_eq!(summary.food_remaining, 0);
+    assert_eq!(ticks, summary.ticks);
+    assert_eq!(simulated_time, summary.simulated_time);
+    assert!(
+        simulation
+            .ants
+            .iter()
+            .all(|ant| ant.state() == AntState::LookingForFood)
+    );
+}
+
+#[test]
+fn test_spawning_towards_food_trails() {
+    let spawn_ants = |spawn_towards_food_trails: bool| {
+        let mut simulation = Simulation::with_spawn_ramp(800., 600., 1.);
+        simulation.spawn_towards_food_trails = spawn_towards_food_trails;
+
+        // a strong food trail leading off to the right of the nest
+        for c in 110..GRID_WIDTH {
+            let ph = simulation.grid.create_pheromone_for_loc(
+                GridLocation::new(75, c),
+                PheromoneType::Food,
+                50.,
+                false,
+            );
+            simulation.grid.deposit_pheromone(ph);
+        }
+
+        simulation.elapsed_time = 0.5;
+        simulation.spawn_scheduled_ants();
+        assert_eq!(simulation.ants.len(), ANT_COUNT / 2);
+
+        // walk them a bit to see which way they're headed
+        let grid = &simulation.grid;
+        simulation
+            .ants
+            .iter_mut()
+            .map(|ant| {
+                let start = ant.rect().center();
+                ant.tick(grid, 0.01);
+                ant.rect().center() - start
+            })
+            .collect::<Vec<Vec2>>()
+    };
+
+    assert!(spawn_ants(true).iter().all(|walked| walked.x > 0.));
+    assert!(spawn_ants(false).iter().any(|walked| walked.x < 0.));
+}
+
+#[test]
+fn test_pheromone_coverage() {
+    let mut simulation = Simulation::new(800., 600.);
+    let cells = (GRID_WIDTH * GRID_HEIGHT) as f32;
+
+    // only the nest is covered, by its own home pheromones
+    assert_eq!(simulation.coverage(PheromoneType::Food), 0.);
+    assert_eq!(
+        simulation.coverage(PheromoneType::Home),
+        (HOME_CELLS * HOME_CELLS) as f32 / cells
+    );
+
+    // a 5x5 block of terrain doesn't count towards the map
+    simulation.spawn_cells(100., 100., CellType::Terrain);
+    for c in 0..10 {
+        let ph = simulation.grid.create_pheromone_for_loc(
+            GridLocation::new(0, c),
+            PheromoneType::Food,
+            1.,
+            false,
+        );
+        simulation.grid.deposit_pheromone(ph);
+    }
+    assert_eq!(
+        simulation.coverage(PheromoneType::Food),
+        10. / (cells - 25.)
+    );
+}
+
+#[test]
+fn test_decay_rate_tuning_counters_saturation() {
+    // the nest alone covers more than the target, but only trails count so the decay rate eases off
+    let mut simulation = Simulation::new(800., 600.);
+    simulation.target_coverage = Some(0.001);
+    let decay_rate = simulation.grid.decay_rate();
+    simulation.step(0.05);
+    assert!(simulation.grid.decay_rate() < decay_rate);
+
+    // trails over the top 40% of the map
+    let mut simulation = Simulation::new(800., 600.);
+    simulation.target_coverage = Some(0.1);
+    for r in 0..60 {
+        for c in 0..GRID_WIDTH {
+            for pheromone_type in [PheromoneType::Food, PheromoneType::Home] {
+                let ph = simulation.grid.create_pheromone_for_loc(
+                    GridLocation::new(r, c),
+                    pheromone_type,
+                    1.,
+                    false,
+                );
+                simulation.grid.deposit_pheromone(ph);
+            }
+        }
+    }
+
+    let mut decay_rate = simulation.grid.decay_rate();
+    for _ in 0..5 {
+        simulation.step(0.05);
+        assert!(simulation.grid.decay_rate() > decay_rate);
+        decay_rate = simulation.grid.decay_rate();
+    }
+}
+
+#[test]
+fn test_heat_frames_are_written_at_interval() {
+    let directory = std::env::temp_dir().join(format!("ants_heat_frames_{}", std::process::id()));
+    let mut simulation = Simulation::new(800., 600.);
+    simulation.export_heat_frames(&directory, 3).unwrap();
+
+    simulation.step_n(0.05, 10);
+
+    let mut frames: Vec<String> = fs::read_dir(&directory)
+        .unwrap()
+        .map(|entry| entry.unwrap().file_name().into_string().unwrap())
+        .collect();
+    frames.sort();
+    // ticks 3, 6 and 9
+    assert_eq!(
+        frames,
+        ["frame_00000.png", "frame_00001.png", "frame_00002.png"]
+    );
+
+    let frame = image::open(directory.join(&frames[2])).unwrap().to_luma8();
+    assert_eq!(frame.dimensions(), (GRID_WIDTH as u32, GRID_HEIGHT as u32));
+    // the nest shows up at full heat
+    let nest = simulation.ant_spawn_point / simulation.grid.cell_width;
+    assert_eq!(frame.get_pixel(nest.x as u32, nest.y as u32).0, [255]);
+
+    // after a reset the new simulation exports to the same directory, continuing the numbering
+    let mut simulation = Simulation::new(800., 600.);
+    simulation.export_heat_frames(&directory, 3).unwrap();
+    simulation.step_n(0.05, 6);
+    let frame_count = fs::read_dir(&directory).unwrap().count();
+    assert_eq!(frame_count, 5);
+    assert!(directory.join("frame_00004.png").exists());
+
+    fs::remove_dir_all(&directory).unwrap();
+}
@@ -1,7 +1,7 @@
 use std::f32::consts::PI;
 
-use macroquad::math::Rect;
-use macroquad::prelude::{Color, draw_rectangle};
+use macroquad::math::{Rect, Vec2};
+use macroquad::prelude::{Color, draw_line, draw_rectangle};
 
 /// Clamps the angle to range -PI to PI
 pub fn normalize_angle(angle: f32) -> f32 {
@@ -25,6 +25,48 @@ impl RectExtensions for Rect {
     }
 }
 
+/// Draws an arrow starting at `origin` pointing in `direction`
+pub fn draw_arrow(origin: Vec2, direction: Vec2, length: f32, color: Color) {
+    let direction = direction.normalize_or_zero();
+    let tip = origin + direction * length;
+    draw_line(origin.x, origin.y, tip.x, tip.y, 1., color);
+
+    // arrowhead
+    let head_length = length / 3.;
+    for side in [-PI / 6., PI / 6.] {
+        let head = tip - Vec2::from_angle(side).rotate(direction) * head_length;
+        draw_line(tip.x, tip.y, head.x, head.y, 1., color);
+    }
+}
+
+/// Smooth 2D value noise in range 0 to 1, with features roughly `scale` units apart
+pub fn value_noise(x: f32, y: f32, scale: f32, seed: u32) -> f32 {
+    let (x, y) = (x / scale, y / scale);
+    let (x0, y0) = (x.floor(), y.floor());
+    let (tx, ty) = (smoothstep(x - x0), smoothstep(y - y0));
+
+    let corner = |dx: i32, dy: i32| lattice_value(x0 as i32 + dx, y0 as i32 + dy, seed);
+    let top = corner(0, 0) + (corner(1, 0) - corner(0, 0)) * tx;
+    let bottom = corner(0, 1) + (corner(1, 1) - corner(0, 1)) * tx;
+    top + (bottom - top) * ty
+}
+
+fn smoothstep(t: f32) -> f32 {
+    t * t * (3. - 2. * t)
+}
+
+/// Pseudo random value in range 0 to 1 for a point of the integer lattice
+fn lattice_value(x: i32, y: i32, seed: u32) -> f32 {
+    let mut h = (x as u32)
+        .wrapping_mul(0x8da6_b343)
+        .wrapping_add((y as u32).wrapping_mul(0xd816_3841))
+        .wrapping_add(seed.wrapping_mul(0xcb1a_b31f));
+    h ^= h >> 13;
+    h = h.wrapping_mul(0x5bd1_e995);
+    h ^= h >> 15;
+    h as f32 / u32::MAX as f32
+}
+
 #[test]
 fn test_normalize_angle() {
     assert_eq!(normalize_angle(PI), PI);